@@ -0,0 +1,144 @@
+use crate::deadzone::Deadzone;
+use crate::gate::Gate;
+use crate::identity::Identity;
+use crate::profile::{self, Profile};
+use crate::virtual_pad::{VirtualPad, STICK_MAX};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Seconds before the ramp starts, to switch focus to the game.
+const COUNTDOWN_SECS: u64 = 5;
+
+/// Ramp speed in fractions of full deflection per second.
+/// Slow enough that ~250ms reaction time overshoots by only ~0.5%,
+/// which conveniently lands the result just past the game's deadzone.
+const RAMP_RATE: f32 = 0.02;
+
+const TICK: Duration = Duration::from_millis(10);
+
+/// Interactive `calibrate-deadzone` mode.
+///
+/// Pushes the output stick slowly to the right from center. The user signals
+/// the moment the camera starts moving — with their `m2joy toggle` keybind
+/// (the game keeps focus) or Enter in this terminal — and the deflection at
+/// that point is saved as the profile's `deadzone.anti`.
+pub fn deadzone(config_path: Option<&std::path::Path>, name: &str, profile: &Profile) {
+    if crate::find_running_instance().is_some() {
+        eprintln!("Another m2joy instance is running; stop it first with `m2joy quit`.");
+        std::process::exit(1);
+    }
+    let path = match config_path {
+        Some(p) => p,
+        None => {
+            eprintln!("No config file location (set HOME or pass --config)");
+            std::process::exit(1);
+        }
+    };
+
+    let identity = Identity::new(&profile.identity, 1);
+    let pad = VirtualPad::new(&identity, Deadzone::default(), Gate::default(), None);
+    let mut pad = match pad {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("Failed to create virtual gamepad: {}", e);
+            log::error!("Do you have /dev/uinput access? Try: sudo modprobe uinput");
+            std::process::exit(1);
+        }
+    };
+    crate::install_retroarch_autoconfig(&identity);
+
+    let enter = Arc::new(AtomicBool::new(false));
+    let enter_clone = Arc::clone(&enter);
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        while matches!(std::io::stdin().read_line(&mut line), Ok(n) if n > 0) {
+            enter_clone.store(true, Ordering::Relaxed);
+        }
+    });
+
+    println!(
+        "Deadzone calibration ({} stick, profile '{}')",
+        profile.stick.name(),
+        name
+    );
+    println!("  1. Switch to the game and stand somewhere you can see the camera turn.");
+    println!("  2. The stick will slowly push right.");
+    println!("  3. The moment the camera starts moving, press your `m2joy toggle` keybind");
+    println!("     (or Enter in this terminal).");
+    println!();
+
+    for s in (1..=COUNTDOWN_SECS).rev() {
+        print!("\rStarting in {}s... ", s);
+        let _ = std::io::stdout().flush();
+        std::thread::sleep(Duration::from_secs(1));
+        if crate::QUIT.load(Ordering::Relaxed) {
+            let _ = pad.emit_stick_raw(profile.stick, 0, 0);
+            return;
+        }
+    }
+    // Ignore presses that happened during the countdown
+    crate::TOGGLE.store(0, Ordering::Relaxed);
+    enter.store(false, Ordering::Relaxed);
+    println!();
+
+    let start = Instant::now();
+    let mut detected = None;
+    loop {
+        if crate::QUIT.load(Ordering::Relaxed) {
+            break;
+        }
+        let level = start.elapsed().as_secs_f32() * RAMP_RATE;
+        if level >= 1.0 {
+            break;
+        }
+        if crate::TOGGLE.swap(0, Ordering::Relaxed) != 0 || enter.swap(false, Ordering::Relaxed) {
+            detected = Some(level);
+            break;
+        }
+        if let Err(e) = pad.emit_stick_raw(profile.stick, (level * STICK_MAX as f32) as i32, 0) {
+            log::warn!("Failed to emit stick: {}", e);
+        }
+        print!("\rDeflection: {:5.1}%", level * 100.0);
+        let _ = std::io::stdout().flush();
+        std::thread::sleep(TICK);
+    }
+    let _ = pad.emit_stick_raw(profile.stick, 0, 0);
+    println!();
+
+    let anti = match detected {
+        Some(level) => level,
+        None => {
+            eprintln!("No key press detected; profile left unchanged.");
+            std::process::exit(1);
+        }
+    };
+    println!("Game deadzone ≈ {:.1}%", anti * 100.0);
+
+    let result = profile::update_profile(path, name, |table| {
+        let dz = table
+            .entry("deadzone")
+            .or_insert_with(toml_edit::table)
+            .as_table_like_mut()
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "'deadzone' is not a table")
+            })?;
+        dz.insert(
+            "anti",
+            toml_edit::value((anti * 1000.0).round() as f64 / 1000.0),
+        );
+        Ok(())
+    });
+    match result {
+        Ok(()) => println!(
+            "Saved deadzone.anti to profile '{}' in {}",
+            name,
+            path.display()
+        ),
+        Err(e) => {
+            log::error!("Failed to save config: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,27 +1,80 @@
-use clap::Parser;
+use crate::device_match::{DeviceMatch, DeviceSelector};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 /// Linux mouse-to-joystick injector for RetroArch (Wayland/evdev).
 /// Grabs your mouse and maps it to a virtual gamepad stick.
+///
+/// Flags given here override the values of the selected profile
+/// in ~/.config/m2joy/config.toml.
 #[derive(Parser, Debug)]
 #[command(name = "m2joy")]
 pub struct Config {
-    /// Mouse sensitivity multiplier
-    #[arg(short, long, default_value_t = 1.0)]
-    pub sensitivity: f32,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Invert Y axis
-    #[arg(long, default_value_t = false)]
-    pub invert_y: bool,
+    /// Named profile from the config file
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
 
-    /// Specific evdev device path (e.g. /dev/input/event5)
-    #[arg(short, long)]
-    pub device: Option<String>,
+    /// Config file path (default: $XDG_CONFIG_HOME/m2joy/config.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
 
-    /// Output to left stick (ABS_X/ABS_Y) instead of right stick (ABS_RX/ABS_RY)
-    #[arg(long, default_value_t = false)]
-    pub left_stick: bool,
+    /// Stick deflection per inch/second of mouse movement (1.0 = full stick at 1 in/s) [default: 0.6]
+    #[arg(short, long, global = true)]
+    pub sensitivity: Option<f32>,
 
-    /// Print debug diagnostics every 100ms (raw deltas, EMA, output)
-    #[arg(long, default_value_t = false)]
+    /// Mouse CPI (counts per inch); overrides the config file [default: 800]
+    #[arg(long, global = true)]
+    pub cpi: Option<f32>,
+
+    /// Invert Y axis; --invert-y=false turns a profile's inversion off
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub invert_y: Option<bool>,
+
+    /// Mouse to use: an evdev path (/dev/input/event5) or a match rule
+    /// (name~="G Pro", vendor=046d,product=c08b, phys=..., uniq=...)
+    #[arg(short, long, global = true)]
+    pub device: Option<DeviceSelector>,
+
+    /// Never use devices matching this rule (repeatable)
+    #[arg(long, global = true)]
+    pub exclude: Vec<DeviceMatch>,
+
+    /// Output to left stick (ABS_X/ABS_Y) instead of right stick (ABS_RX/ABS_RY);
+    /// --left-stick=false picks the right stick over the profile's
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub left_stick: Option<bool>,
+
+    /// Main loop frequency in Hz, 250-8000 [default: 1000]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(250..=8000))]
+    pub tick_rate: Option<u32>,
+
+    /// Print debug diagnostics every 100ms (raw deltas, filtered velocity, output)
+    #[arg(long, default_value_t = false, global = true)]
     pub debug: bool,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Grab or release the mouse in the running instance (sends SIGUSR1)
+    Toggle {
+        /// Only this player (1-based); all players when omitted
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..=16))]
+        player: Option<u32>,
+    },
+    /// Stop the running instance
+    Quit,
+    /// Show each player's grab state, profile and layer in the running instance
+    Status,
+    /// Measure the mouse's report rate and counts per report
+    Probe,
+    /// Show every input device and whether m2joy would use it, and whether
+    /// another program has grabbed each pointer device
+    ListDevices,
+    /// Move or click the mouse to use, then save a match rule for it to the profile
+    PickDevice,
+    /// Ramp the stick slowly to find the game's deadzone, then save it to the profile
+    CalibrateDeadzone,
+}
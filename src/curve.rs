@@ -0,0 +1,189 @@
+use serde::Deserialize;
+
+/// Transfer curve from mouse velocity to stick deflection.
+///
+/// Curves work on normalized magnitudes: an input of 1.0 is the deflection the
+/// linear mapping (`sensitivity * velocity / cpi`) would produce at full stick,
+/// and an output of 1.0 is full stick. They are applied to the length of the
+/// (x, y) vector, never per axis, so the aim direction is preserved.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Curve {
+    /// `offset + v`, limited to `cap` (unlimited by default).
+    /// Any non-zero input starts at `offset`.
+    Linear {
+        #[serde(default)]
+        offset: f32,
+        #[serde(default = "default_cap")]
+        cap: f32,
+    },
+    /// `v^exponent`. Exponents above 1 give finer control near center.
+    Power { exponent: f32 },
+    /// Logistic S-curve through `midpoint`, rescaled so 0 maps to 0 and
+    /// the output approaches 1. `steepness` controls how sharp the ramp is.
+    Sigmoid { midpoint: f32, steepness: f32 },
+    /// Piecewise-linear lookup through `[input, output]` points, sorted by input.
+    /// Inputs past the last point hold its output.
+    Table { points: Vec<[f32; 2]> },
+}
+
+fn default_cap() -> f32 {
+    f32::INFINITY
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::Linear {
+            offset: 0.0,
+            cap: default_cap(),
+        }
+    }
+}
+
+impl Curve {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Curve::Linear { offset, cap } if *offset < 0.0 || *cap <= 0.0 => {
+                Err("linear curve needs offset >= 0 and cap > 0".into())
+            }
+            Curve::Power { exponent } if *exponent <= 0.0 => {
+                Err("power curve needs exponent > 0".into())
+            }
+            Curve::Sigmoid { steepness, .. } if *steepness <= 0.0 => {
+                Err("sigmoid curve needs steepness > 0".into())
+            }
+            Curve::Table { points } => {
+                if points.len() < 2 {
+                    return Err("table curve needs at least two points".into());
+                }
+                if points.windows(2).any(|w| w[1][0] <= w[0][0]) {
+                    return Err("table curve points must have strictly increasing inputs".into());
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Map a normalized velocity magnitude (>= 0) to a normalized deflection.
+    pub fn eval(&self, v: f32) -> f32 {
+        if v <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            Curve::Linear { offset, cap } => (offset + v).min(*cap),
+            Curve::Power { exponent } => v.powf(*exponent),
+            Curve::Sigmoid {
+                midpoint,
+                steepness,
+            } => {
+                let s = |x: f32| 1.0 / (1.0 + (-steepness * (x - midpoint)).exp());
+                let s0 = s(0.0);
+                (s(v) - s0) / (1.0 - s0)
+            }
+            Curve::Table { points } => {
+                let first = points[0];
+                if v <= first[0] {
+                    // Interpolate from the origin to the first point
+                    return if first[0] > 0.0 {
+                        first[1] * v / first[0]
+                    } else {
+                        first[1]
+                    };
+                }
+                for w in points.windows(2) {
+                    let ([x0, y0], [x1, y1]) = (w[0], w[1]);
+                    if v <= x1 {
+                        return y0 + (y1 - y0) * (v - x0) / (x1 - x0);
+                    }
+                }
+                points[points.len() - 1][1]
+            }
+        }
+    }
+
+    /// Apply the curve to the magnitude of a normalized (x, y) vector.
+    pub fn shape(&self, x: f32, y: f32) -> (f32, f32) {
+        let mag = x.hypot(y);
+        if mag == 0.0 {
+            return (0.0, 0.0);
+        }
+        let k = self.eval(mag) / mag;
+        (x * k, y * k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curves() -> Vec<Curve> {
+        vec![
+            Curve::default(),
+            Curve::Linear {
+                offset: 0.1,
+                cap: 0.8,
+            },
+            Curve::Power { exponent: 0.5 },
+            Curve::Power { exponent: 2.0 },
+            Curve::Sigmoid {
+                midpoint: 0.5,
+                steepness: 10.0,
+            },
+            Curve::Table {
+                points: vec![[0.2, 0.1], [0.5, 0.6], [1.0, 1.0]],
+            },
+        ]
+    }
+
+    #[test]
+    fn every_curve_is_monotonic() {
+        for curve in curves() {
+            let mut last = curve.eval(0.0);
+            for i in 1..=300 {
+                let y = curve.eval(i as f32 / 100.0);
+                assert!(y >= last, "{:?} falls at {}", curve, i);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn zero_maps_to_zero() {
+        for curve in curves() {
+            assert_eq!(curve.eval(0.0), 0.0, "{:?}", curve);
+            assert_eq!(curve.shape(0.0, 0.0), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(Curve::default().eval(1.0), 1.0);
+        assert_eq!(Curve::Power { exponent: 2.0 }.eval(1.0), 1.0);
+        let linear = Curve::Linear {
+            offset: 0.1,
+            cap: 0.8,
+        };
+        assert!((linear.eval(0.001) - 0.101).abs() < 1e-6);
+        assert_eq!(linear.eval(5.0), 0.8);
+        let sigmoid = Curve::Sigmoid {
+            midpoint: 0.5,
+            steepness: 10.0,
+        };
+        assert!((sigmoid.eval(0.5) - 0.5).abs() < 0.01);
+        assert!(sigmoid.eval(2.0) > 0.99);
+        let table = Curve::Table {
+            points: vec![[0.2, 0.1], [0.5, 0.6], [1.0, 1.0]],
+        };
+        assert!((table.eval(0.1) - 0.05).abs() < 1e-6);
+        assert_eq!(table.eval(0.5), 0.6);
+        assert_eq!(table.eval(3.0), 1.0);
+    }
+
+    #[test]
+    fn shape_keeps_direction() {
+        let (x, y) = Curve::Power { exponent: 2.0 }.shape(0.3, -0.4);
+        assert!((x.hypot(y) - 0.25).abs() < 1e-6);
+        assert!((y / x - -0.4 / 0.3).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,88 @@
+use serde::Deserialize;
+
+/// Radial deadzone compensation applied to the final stick vector.
+///
+/// Both values are fractions of full deflection. An input of `outer` reaches
+/// full stick (anything past it is clipped by the output clamp), and every
+/// non-zero input is remapped so the smallest output lands at `anti`, just
+/// past the game's own deadzone, instead of disappearing inside it.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deadzone {
+    pub anti: f32,
+    pub outer: f32,
+}
+
+impl Default for Deadzone {
+    fn default() -> Self {
+        Self {
+            anti: 0.0,
+            outer: 1.0,
+        }
+    }
+}
+
+impl Deadzone {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..1.0).contains(&self.anti) {
+            return Err("deadzone.anti must be in [0, 1)".into());
+        }
+        if self.outer <= 0.0 || self.outer > 1.0 {
+            return Err("deadzone.outer must be in (0, 1]".into());
+        }
+        Ok(())
+    }
+
+    /// Remap a normalized (x, y) vector. Direction is preserved.
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let mag = x.hypot(y);
+        if mag == 0.0 {
+            return (0.0, 0.0);
+        }
+        let out = self.anti + (1.0 - self.anti) * mag / self.outer;
+        let k = out / mag;
+        (x * k, y * k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DZ: Deadzone = Deadzone {
+        anti: 0.2,
+        outer: 0.8,
+    };
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn zero_stays_centred() {
+        assert_eq!(DZ.apply(0.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn smallest_input_lands_just_past_anti() {
+        let (x, _) = DZ.apply(1e-5, 0.0);
+        assert!(x > 0.2 && x < 0.2001, "{}", x);
+        let (x, y) = DZ.apply(0.0, -1e-5);
+        assert!(x == 0.0 && y < -0.2 && y > -0.2001, "{}", y);
+    }
+
+    #[test]
+    fn outer_reaches_full_stick() {
+        assert!(close(DZ.apply(0.8, 0.0), (1.0, 0.0)));
+        // Past outer is left for the output clamp
+        assert!(close(DZ.apply(1.0, 0.0), (1.2, 0.0)));
+    }
+
+    #[test]
+    fn default_is_identity_and_direction_is_kept() {
+        assert!(close(Deadzone::default().apply(0.3, -0.4), (0.3, -0.4)));
+        let (x, y) = DZ.apply(0.3, 0.4);
+        assert!((y / x - 0.4 / 0.3).abs() < 1e-5);
+        assert!((x.hypot(y) - (0.2 + 0.8 * 0.5 / 0.8)).abs() < 1e-6);
+    }
+}
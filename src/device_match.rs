@@ -0,0 +1,387 @@
+use crate::identity::PAD_NAME;
+use crate::mouse::DeviceInfo;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Vendor,
+    Product,
+    Phys,
+    Uniq,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    /// `key=value`: exact string match.
+    Is(Field, String),
+    /// `key~=value`: case-insensitive substring match.
+    Contains(Field, String),
+    /// `vendor=` / `product=`: hex ID.
+    Id(Field, u16),
+}
+
+/// A device match rule such as `name~="Logitech G Pro"` or
+/// `vendor=046d,product=c08b`. Every comma-separated term must match.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct DeviceMatch {
+    terms: Vec<Term>,
+}
+
+impl DeviceMatch {
+    pub fn matches(&self, info: &DeviceInfo) -> bool {
+        self.terms.iter().all(|term| match term {
+            Term::Is(field, value) => text(info, *field) == value,
+            Term::Contains(field, value) => text(info, *field)
+                .to_lowercase()
+                .contains(&value.to_lowercase()),
+            Term::Id(Field::Vendor, id) => info.vendor == *id,
+            Term::Id(_, id) => info.product == *id,
+        })
+    }
+
+    /// Rule that selects exactly this device: name and IDs, plus the serial
+    /// when the device has one.
+    pub fn identity(info: &DeviceInfo) -> Self {
+        let mut terms = vec![
+            Term::Is(Field::Name, info.name.clone()),
+            Term::Id(Field::Vendor, info.vendor),
+            Term::Id(Field::Product, info.product),
+        ];
+        if !info.uniq.is_empty() {
+            terms.push(Term::Is(Field::Uniq, info.uniq.clone()));
+        }
+        Self { terms }
+    }
+}
+
+fn text(info: &DeviceInfo, field: Field) -> &str {
+    match field {
+        Field::Name => &info.name,
+        Field::Phys => &info.phys,
+        Field::Uniq => &info.uniq,
+        Field::Vendor | Field::Product => unreachable!("IDs are matched as numbers"),
+    }
+}
+
+impl FromStr for DeviceMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut terms = Vec::new();
+        for part in split_terms(s)? {
+            let (key, op_contains, value) = match part.split_once('=') {
+                Some((k, v)) if k.ends_with('~') => (&k[..k.len() - 1], true, v),
+                Some((k, v)) => (k, false, v),
+                None => return Err(format!("'{}': expected key=value or key~=value", part)),
+            };
+            let value = unquote(value.trim());
+            let field = match key.trim() {
+                "name" => Field::Name,
+                "vendor" => Field::Vendor,
+                "product" => Field::Product,
+                "phys" => Field::Phys,
+                "uniq" => Field::Uniq,
+                other => {
+                    return Err(format!(
+                        "unknown match key '{}' (expected name, vendor, product, phys or uniq)",
+                        other
+                    ))
+                }
+            };
+            terms.push(match field {
+                Field::Vendor | Field::Product if op_contains => {
+                    return Err(format!("'{}': IDs only support '='", part));
+                }
+                Field::Vendor | Field::Product => {
+                    let hex = value.trim_start_matches("0x");
+                    let id = u16::from_str_radix(hex, 16)
+                        .map_err(|_| format!("'{}': expected a hex ID like 046d", part))?;
+                    Term::Id(field, id)
+                }
+                _ if op_contains => Term::Contains(field, value),
+                _ => Term::Is(field, value),
+            });
+        }
+        if terms.is_empty() {
+            return Err("empty device match".into());
+        }
+        Ok(Self { terms })
+    }
+}
+
+/// Split on commas outside double quotes. Inside them, a backslash escapes
+/// the next character.
+fn split_terms(s: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quote in '{}'", s));
+    }
+    parts.push(&s[start..]);
+    Ok(parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect())
+}
+
+/// Strip the quotes around a value, undoing the `\"` and `\\` escapes
+/// inside them.
+fn unquote(s: &str) -> String {
+    let Some(inner) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+        return s.to_string();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => chars.next().unwrap_or(c),
+            _ => c,
+        });
+    }
+    out
+}
+
+/// A value in double quotes, escaped so `unquote` gives it back.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            let key = |field| match field {
+                Field::Name => "name",
+                Field::Vendor => "vendor",
+                Field::Product => "product",
+                Field::Phys => "phys",
+                Field::Uniq => "uniq",
+            };
+            match term {
+                Term::Is(field, v) => write!(f, "{}={}", key(*field), quote(v))?,
+                Term::Contains(field, v) => write!(f, "{}~={}", key(*field), quote(v))?,
+                Term::Id(field, id) => write!(f, "{}={:04x}", key(*field), id)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for DeviceMatch {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// `--device` / `device =`: an event node path or a match rule.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub enum DeviceSelector {
+    Path(PathBuf),
+    Match(DeviceMatch),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.starts_with('/') {
+            Ok(DeviceSelector::Path(PathBuf::from(s)))
+        } else {
+            s.parse().map(DeviceSelector::Match)
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceSelector {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// What a filter looks for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceKind {
+    #[default]
+    Mouse,
+    Keyboard,
+    Gamepad,
+}
+
+impl DeviceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceKind::Mouse => "mouse",
+            DeviceKind::Keyboard => "keyboard",
+            DeviceKind::Gamepad => "gamepad",
+        }
+    }
+}
+
+/// Why a device is or is not picked for a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Match,
+    NotPointer,
+    NotKeyboard,
+    NotGamepad,
+    Virtual,
+    Excluded,
+    NoMatch,
+}
+
+impl Verdict {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Verdict::Match => "match",
+            Verdict::NotPointer => "not a mouse",
+            Verdict::NotKeyboard => "not a keyboard",
+            Verdict::NotGamepad => "not a gamepad",
+            Verdict::Virtual => "virtual device",
+            Verdict::Excluded => "excluded",
+            Verdict::NoMatch => "does not match rule",
+        }
+    }
+}
+
+/// Which event nodes a source may use: devices of the filter's `kind` (see
+/// `is_kind`) that match the rule (any, if none) and none of the excludes.
+/// Virtual (uinput) devices, including our own pad, are always skipped.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub kind: DeviceKind,
+    pub rule: Option<DeviceMatch>,
+    pub exclude: Vec<DeviceMatch>,
+}
+
+impl DeviceFilter {
+    /// `capable`: the device is of the filter's kind (see `is_kind`).
+    pub fn check(&self, path: &Path, info: &DeviceInfo, capable: bool) -> Verdict {
+        if !capable {
+            match self.kind {
+                DeviceKind::Mouse => Verdict::NotPointer,
+                DeviceKind::Keyboard => Verdict::NotKeyboard,
+                DeviceKind::Gamepad => Verdict::NotGamepad,
+            }
+        } else if info.name.starts_with(PAD_NAME) || is_virtual(path) {
+            Verdict::Virtual
+        } else if self.exclude.iter().any(|m| m.matches(info)) {
+            Verdict::Excluded
+        } else if self.rule.as_ref().is_some_and(|m| !m.matches(info)) {
+            Verdict::NoMatch
+        } else {
+            Verdict::Match
+        }
+    }
+}
+
+/// uinput devices live under /sys/devices/virtual.
+fn is_virtual(path: &Path) -> bool {
+    let Some(node) = path.file_name() else {
+        return false;
+    };
+    std::fs::canonicalize(Path::new("/sys/class/input").join(node))
+        .is_ok_and(|p| p.starts_with("/sys/devices/virtual"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, uniq: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            vendor: 0x046d,
+            product: 0xc08b,
+            phys: "usb-0000:00:14.0-2/input0".to_string(),
+            uniq: uniq.to_string(),
+        }
+    }
+
+    fn round_trip(rule: &DeviceMatch) {
+        let shown = rule.to_string();
+        assert_eq!(shown.parse::<DeviceMatch>().as_ref(), Ok(rule), "{}", shown);
+    }
+
+    #[test]
+    fn identity_survives_display_and_parse() {
+        for (name, uniq) in [
+            ("Logitech G Pro", ""),
+            ("Mouse, with a comma", "ABC123"),
+            (r#"The "Best" Mouse"#, ""),
+            (r#"Back\slash \"trick"#, r#"a"b,c"#),
+        ] {
+            let device = info(name, uniq);
+            let rule = DeviceMatch::identity(&device);
+            round_trip(&rule);
+            assert!(rule
+                .to_string()
+                .parse::<DeviceMatch>()
+                .unwrap()
+                .matches(&device));
+        }
+    }
+
+    #[test]
+    fn parsed_rules_survive_display_and_parse() {
+        for s in [
+            r#"name~="g pro",vendor=046d"#,
+            r#"phys~="usb-0000:00:14.0-2",uniq=ABC123"#,
+            r#"name="say \"hi\"""#,
+            "product=0xC08B",
+        ] {
+            round_trip(&s.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn escapes_are_undone_inside_quotes_only() {
+        let rule: DeviceMatch = r#"name="a\"b\\c""#.parse().unwrap();
+        assert!(rule.matches(&info(r#"a"b\c"#, "")));
+        let rule: DeviceMatch = r"name=a\b".parse().unwrap();
+        assert!(rule.matches(&info(r"a\b", "")));
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        for s in [
+            "vendor~=046d",
+            "vendor=xyz",
+            "product=12345",
+            r#"name="G Pro"#,
+            r#"name="G Pro\""#,
+            "name",
+            "color=red",
+            "",
+            " , ",
+        ] {
+            assert!(s.parse::<DeviceMatch>().is_err(), "{}", s);
+        }
+    }
+}
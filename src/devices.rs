@@ -0,0 +1,293 @@
+use crate::device_match::{DeviceFilter, DeviceKind, DeviceMatch, DeviceSelector, Verdict};
+use crate::gamepad::is_gamepad;
+use crate::hotplug;
+use crate::mouse::{is_keyboard, is_kind, is_pointer, DeviceInfo};
+use crate::profile::{self, Profile};
+use evdev::{Device, InputEventKind};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// Motion (in counts, summed over both axes) that counts as "this one moved".
+/// Enough to ignore sensor noise and a bumped desk.
+const PICK_MOTION: i32 = 200;
+
+/// How long `m2joy pick-device` waits for input.
+const PICK_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct Row {
+    path: PathBuf,
+    info: DeviceInfo,
+    capabilities: String,
+    /// Only probed on pointer devices
+    grabbed: Option<&'static str>,
+    /// Verdict for each of the profile's mice.
+    verdicts: Vec<Verdict>,
+}
+
+/// `m2joy list-devices`: every event node, what it is, and whether the
+/// current profile would use it.
+pub fn list(profile: &Profile) {
+    // Every mouse, then the keyboard and gamepad if the profile has them
+    let mice = profile.mice();
+    let mut filters: Vec<_> = mice.iter().map(|m| profile.device_filter(m)).collect();
+    let mut selectors: Vec<_> = mice.iter().map(|m| m.device.clone()).collect();
+    if let (Some(keyboard), Some(filter)) = (&profile.keyboard, profile.keyboard_filter()) {
+        filters.push(filter);
+        selectors.push(keyboard.device.clone());
+    }
+    if let (Some(gamepad), Some(filter)) = (&profile.gamepad, profile.gamepad_filter()) {
+        filters.push(filter);
+        selectors.push(gamepad.device.clone());
+    }
+    let label = |i: usize| {
+        if filters[i].kind != DeviceKind::Mouse {
+            format!("{}:", filters[i].kind.name())
+        } else if mice.len() > 1 {
+            format!("mouse {}:", i + 1)
+        } else {
+            "m2joy:".to_string()
+        }
+    };
+    let mut rows = Vec::new();
+    for path in hotplug::event_nodes() {
+        let mut device = match Device::open(&path) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("{}  (cannot open: {})", path.display(), e);
+                println!();
+                continue;
+            }
+        };
+        let info = DeviceInfo::of(&device);
+        rows.push(Row {
+            verdicts: filters
+                .iter()
+                .map(|f| f.check(&path, &info, is_kind(&device, f.kind)))
+                .collect(),
+            capabilities: capabilities(&device),
+            // Grabbing a keyboard or pad, even briefly, can drop a key
+            // press another program was reading
+            grabbed: is_pointer(&device).then(|| grabbed(&mut device)),
+            path,
+            info,
+        });
+    }
+
+    // Same order as startup: each device takes the first match the ones
+    // before it did not.
+    let mut selected: Vec<Option<PathBuf>> = Vec::new();
+    for (i, selector) in selectors.iter().enumerate() {
+        let pick = match selector {
+            Some(DeviceSelector::Path(p)) => Some(p.clone()),
+            _ => rows
+                .iter()
+                .find(|r| {
+                    r.verdicts[i] == Verdict::Match && !selected.contains(&Some(r.path.clone()))
+                })
+                .map(|r| r.path.clone()),
+        };
+        selected.push(pick);
+    }
+
+    for row in &rows {
+        println!(
+            "{}  {}  [{:04x}:{:04x}]",
+            row.path.display(),
+            row.info.name,
+            row.info.vendor,
+            row.info.product
+        );
+        println!("    phys:         {}", or_dash(&row.info.phys));
+        println!("    uniq:         {}", or_dash(&row.info.uniq));
+        println!("    capabilities: {}", row.capabilities);
+        if let Some(grabbed) = row.grabbed {
+            println!("    grabbed:      {}", grabbed);
+        }
+        for (i, verdict) in row.verdicts.iter().enumerate() {
+            let label = label(i);
+            let mark = if selected[i].as_ref() == Some(&row.path) {
+                " <- selected"
+            } else {
+                ""
+            };
+            println!("    {:<13} {}{}", label, verdict.describe(), mark);
+        }
+        println!();
+    }
+    for (i, pick) in selected.iter().enumerate() {
+        let label = if filters[i].kind != DeviceKind::Mouse {
+            format!("The {}", filters[i].kind.name())
+        } else if mice.len() > 1 {
+            format!("Mouse {}", i + 1)
+        } else {
+            "The mouse".to_string()
+        };
+        match (&selectors[i], pick) {
+            (Some(DeviceSelector::Path(p)), _) => {
+                println!(
+                    "{} uses {} directly; matching is skipped.",
+                    label,
+                    p.display()
+                )
+            }
+            (_, None) => println!("{}: no device matches; m2joy would not start.", label),
+            _ => {}
+        }
+    }
+}
+
+fn or_dash(s: &str) -> &str {
+    if s.is_empty() {
+        "-"
+    } else {
+        s
+    }
+}
+
+fn capabilities(device: &Device) -> String {
+    let mut caps = Vec::new();
+    if is_pointer(device) {
+        caps.push("pointer".to_string());
+    }
+    if is_keyboard(device) {
+        caps.push("keyboard".to_string());
+    }
+    if is_gamepad(device) {
+        caps.push("gamepad".to_string());
+    }
+    if let Some(rel) = device.supported_relative_axes() {
+        caps.push(format!("{} rel axes", rel.iter().count()));
+    }
+    if let Some(abs) = device.supported_absolute_axes() {
+        caps.push(format!("{} abs axes", abs.iter().count()));
+    }
+    if let Some(keys) = device.supported_keys() {
+        caps.push(format!("{} keys", keys.iter().count()));
+    }
+    if caps.is_empty() {
+        "-".into()
+    } else {
+        caps.join(", ")
+    }
+}
+
+/// Whether another process holds an exclusive grab. Probed by grabbing and
+/// immediately releasing; EBUSY means someone else has it.
+fn grabbed(device: &mut Device) -> &'static str {
+    match device.grab() {
+        Ok(()) => {
+            let _ = device.ungrab();
+            "no"
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EBUSY) => "yes",
+        Err(_) => "unknown",
+    }
+}
+
+/// `m2joy pick-device`: watch every pointer device, take the first one the
+/// user moves or clicks, and save a match rule for it to the profile.
+pub fn pick(config_path: Option<&std::path::Path>, name: &str) {
+    let path = match config_path {
+        Some(p) => p,
+        None => {
+            eprintln!("No config file location (set HOME or pass --config)");
+            std::process::exit(1);
+        }
+    };
+
+    // Every real pointer device, ignoring the profile's rule and excludes:
+    // picking is how those get fixed.
+    let any = DeviceFilter::default();
+    let mut candidates: Vec<(PathBuf, Device, DeviceInfo, i32)> = hotplug::event_nodes()
+        .into_iter()
+        .filter_map(|node| {
+            let device = Device::open(&node).ok()?;
+            let info = DeviceInfo::of(&device);
+            (any.check(&node, &info, is_pointer(&device)) == Verdict::Match)
+                .then_some((node, device, info, 0))
+        })
+        .collect();
+    if candidates.is_empty() {
+        eprintln!("No pointer devices found. Are you in the 'input' group?");
+        std::process::exit(1);
+    }
+    for (_, device, _, _) in &candidates {
+        let fd = device.as_raw_fd();
+        unsafe {
+            libc::fcntl(
+                fd,
+                libc::F_SETFL,
+                libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+            )
+        };
+    }
+
+    println!("Watching {} pointer devices:", candidates.len());
+    for (node, _, info, _) in &candidates {
+        println!("  {}  {}", node.display(), info.name);
+    }
+    println!();
+    println!("Move or click the mouse you want m2joy to use...");
+
+    let start = Instant::now();
+    let chosen = 'wait: loop {
+        if start.elapsed() > PICK_TIMEOUT || crate::QUIT.load(Ordering::Relaxed) {
+            break None;
+        }
+        let mut fds: Vec<libc::pollfd> = candidates
+            .iter()
+            .map(|(_, device, _, _)| libc::pollfd {
+                fd: device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 200) };
+
+        for (i, (_, device, _, motion)) in candidates.iter_mut().enumerate() {
+            if fds[i].revents == 0 {
+                continue;
+            }
+            let Ok(events) = device.fetch_events() else {
+                continue;
+            };
+            for ev in events {
+                match ev.kind() {
+                    InputEventKind::RelAxis(_) => *motion += ev.value().abs(),
+                    InputEventKind::Key(_) if ev.value() == 1 => break 'wait Some(i),
+                    _ => {}
+                }
+            }
+            if *motion >= PICK_MOTION {
+                break 'wait Some(i);
+            }
+        }
+    };
+
+    let Some(i) = chosen else {
+        eprintln!("No input detected; profile left unchanged.");
+        std::process::exit(1);
+    };
+    let (node, _, info, _) = &candidates[i];
+    let rule = DeviceMatch::identity(info);
+    println!("Picked {} ({})", info.name, node.display());
+
+    let result = profile::update_profile(path, name, |table| {
+        table.insert("device", toml_edit::value(rule.to_string()));
+        Ok(())
+    });
+    match result {
+        Ok(()) => println!(
+            "Saved device = '{}' to profile '{}' in {}",
+            rule,
+            name,
+            path.display()
+        ),
+        Err(e) => {
+            log::error!("Failed to save config: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
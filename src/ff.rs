@@ -0,0 +1,432 @@
+use evdev::{
+    AttributeSet, AttributeSetRef, Device, FFEffect, FFEffectData, FFEffectKind, FFEffectType,
+    FFWaveform,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+/// Effects a game can have uploaded to the virtual pad at once.
+pub const FF_EFFECTS_MAX: u32 = 16;
+
+/// Effect types the virtual pad offers when they do not go to a physical
+/// pad: rumble, and the periodic waveforms games fall back to without it.
+const OFFERED: [FFEffectType; 8] = [
+    FFEffectType::FF_RUMBLE,
+    FFEffectType::FF_PERIODIC,
+    FFEffectType::FF_SQUARE,
+    FFEffectType::FF_TRIANGLE,
+    FFEffectType::FF_SINE,
+    FFEffectType::FF_SAW_UP,
+    FFEffectType::FF_SAW_DOWN,
+    FFEffectType::FF_GAIN,
+];
+
+/// What happens to the force feedback games send the virtual pad.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FfConfig {
+    pub sink: FfSink,
+    /// Shell command run for every effect played or stopped, with
+    /// `sink = "hook"`.
+    pub command: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FfSink {
+    /// Played on the merged physical pad; dropped without one.
+    #[default]
+    Gamepad,
+    /// Logged.
+    Log,
+    /// Run `command`.
+    Hook,
+    /// The virtual pad offers no force feedback.
+    Off,
+}
+
+impl FfConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.sink, &self.command) {
+            (FfSink::Hook, None) => Err("ff: sink = \"hook\" needs a command".into()),
+            (FfSink::Hook, Some(_)) | (_, None) => Ok(()),
+            (_, Some(_)) => Err("ff: command is only used with sink = \"hook\"".into()),
+        }
+    }
+
+    /// Effect types the virtual pad offers: the physical pad's when effects
+    /// go to it, the common rumble set otherwise, none when off.
+    pub fn offered(
+        &self,
+        physical: Option<&AttributeSetRef<FFEffectType>>,
+    ) -> Option<AttributeSet<FFEffectType>> {
+        match (self.sink, physical) {
+            (FfSink::Off, _) => None,
+            (FfSink::Gamepad, Some(types)) => Some(forwardable(types)),
+            _ => {
+                let mut types = AttributeSet::new();
+                for t in OFFERED {
+                    types.insert(t);
+                }
+                Some(types)
+            }
+        }
+    }
+}
+
+/// A force feedback request a game made of the virtual pad, by the effect
+/// ID the kernel gave it.
+#[derive(Debug, Clone, Copy)]
+pub enum FfCommand {
+    /// New or changed effect.
+    Upload(u16, FFEffectData),
+    Erase(u16),
+    /// Play `count` times; 0 stops.
+    Play(u16, i32),
+    /// Overall strength, 0 to 0xffff.
+    Gain(u16),
+}
+
+/// Effect types to offer on the virtual pad for a physical pad's: all it
+/// has except custom waveforms, which cannot be forwarded.
+fn forwardable(physical: &AttributeSetRef<FFEffectType>) -> AttributeSet<FFEffectType> {
+    let mut types = AttributeSet::new();
+    for t in physical.iter().filter(|&t| t != FFEffectType::FF_CUSTOM) {
+        types.insert(t);
+    }
+    types
+}
+
+/// Queue of requests for the physical pad's reader thread, which plays
+/// them: while it holds the grab, the kernel ignores effects played through
+/// any other handle. Sending never blocks; an eventfd wakes the reader.
+pub fn channel() -> std::io::Result<(FfSender, FfReceiver)> {
+    let raw = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if raw < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let wake = Arc::new(unsafe { OwnedFd::from_raw_fd(raw) });
+    let (tx, rx) = mpsc::channel();
+    Ok((
+        FfSender {
+            tx,
+            wake: Arc::clone(&wake),
+        },
+        FfReceiver { rx, wake },
+    ))
+}
+
+pub struct FfSender {
+    tx: Sender<FfCommand>,
+    wake: Arc<OwnedFd>,
+}
+
+impl FfSender {
+    pub fn send(&self, command: FfCommand) {
+        if self.tx.send(command).is_ok() {
+            let one: u64 = 1;
+            unsafe { libc::write(self.wake.as_raw_fd(), (&one as *const u64).cast(), 8) };
+        }
+    }
+}
+
+pub struct FfReceiver {
+    rx: Receiver<FfCommand>,
+    wake: Arc<OwnedFd>,
+}
+
+impl FfReceiver {
+    /// Readable (for poll) while requests are waiting.
+    pub fn fd(&self) -> libc::c_int {
+        self.wake.as_raw_fd()
+    }
+
+    /// Take every waiting request.
+    pub fn drain(&self) -> Vec<FfCommand> {
+        let mut count: u64 = 0;
+        unsafe { libc::read(self.wake.as_raw_fd(), (&mut count as *mut u64).cast(), 8) };
+        self.rx.try_iter().collect()
+    }
+}
+
+/// The effects uploaded to a physical pad, by the virtual pad's IDs.
+#[derive(Default)]
+pub struct FfEffects {
+    effects: HashMap<u16, FFEffect>,
+    /// Every effect's data, to upload again after a reconnect.
+    data: HashMap<u16, FFEffectData>,
+}
+
+impl FfEffects {
+    pub fn apply(&mut self, device: &mut Device, command: FfCommand) -> std::io::Result<()> {
+        match command {
+            FfCommand::Upload(id, data) => {
+                self.data.insert(id, data);
+                match self.effects.get_mut(&id) {
+                    Some(effect) => effect.update(data)?,
+                    None => {
+                        self.effects.insert(id, device.upload_ff_effect(data)?);
+                    }
+                }
+            }
+            FfCommand::Erase(id) => {
+                self.data.remove(&id);
+                // Dropping the effect erases it from the pad
+                self.effects.remove(&id);
+            }
+            FfCommand::Play(id, count) => {
+                if let Some(effect) = self.effects.get_mut(&id) {
+                    if count > 0 {
+                        effect.play(count)?;
+                    } else {
+                        effect.stop()?;
+                    }
+                }
+            }
+            FfCommand::Gain(gain) => device.set_ff_gain(gain)?,
+        }
+        Ok(())
+    }
+
+    /// Upload every effect to a reopened device.
+    pub fn reupload(&mut self, device: &mut Device) {
+        self.effects.clear();
+        for (&id, &data) in &self.data {
+            match device.upload_ff_effect(data) {
+                Ok(effect) => {
+                    self.effects.insert(id, effect);
+                }
+                Err(e) => log::warn!("Failed to upload force feedback effect: {}", e),
+            }
+        }
+    }
+}
+
+/// Logs or runs the hook for the virtual pad's effects on a thread of its
+/// own, so neither a slow terminal nor a slow command holds up the loop.
+pub struct FfReporter {
+    tx: Sender<FfCommand>,
+}
+
+impl FfReporter {
+    /// The reporter for a log or hook sink; None for the others.
+    pub fn spawn(config: &FfConfig, player: usize, label: String) -> std::io::Result<Option<Self>> {
+        let hook = match config.sink {
+            FfSink::Log => None,
+            FfSink::Hook => config.command.clone(),
+            FfSink::Gamepad | FfSink::Off => return Ok(None),
+        };
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("ff-reporter".into())
+            .spawn(move || report(rx, player, &label, hook.as_deref()))?;
+        Ok(Some(Self { tx }))
+    }
+
+    pub fn send(&self, command: FfCommand) {
+        let _ = self.tx.send(command);
+    }
+}
+
+/// Until the player goes away: keep the uploaded effects, and log or run
+/// `hook` for each play, stop and gain change.
+fn report(rx: Receiver<FfCommand>, player: usize, label: &str, hook: Option<&str>) {
+    let mut effects: HashMap<u16, FFEffectData> = HashMap::new();
+    let mut children: Vec<Child> = Vec::new();
+    for command in rx {
+        let mut env = vec![("M2JOY_PLAYER", player.to_string())];
+        match command {
+            FfCommand::Upload(id, data) => {
+                log::debug!(
+                    "{}Force feedback: effect {} is {}",
+                    label,
+                    id,
+                    describe(&data)
+                );
+                effects.insert(id, data);
+                continue;
+            }
+            FfCommand::Erase(id) => {
+                effects.remove(&id);
+                continue;
+            }
+            FfCommand::Play(id, count) => {
+                let Some(data) = effects.get(&id) else {
+                    continue;
+                };
+                let event = if count > 0 { "play" } else { "stop" };
+                if hook.is_none() {
+                    if count > 0 {
+                        log::info!(
+                            "{}Force feedback: play effect {} x{}: {}",
+                            label,
+                            id,
+                            count,
+                            describe(data)
+                        );
+                    } else {
+                        log::info!("{}Force feedback: stop effect {}", label, id);
+                    }
+                }
+                let (strong, weak) = motors(data);
+                env.extend([
+                    ("M2JOY_FF_EVENT", event.to_string()),
+                    ("M2JOY_FF_EFFECT", id.to_string()),
+                    ("M2JOY_FF_TYPE", kind_name(data).to_string()),
+                    ("M2JOY_FF_STRONG", format!("{:.3}", strong)),
+                    ("M2JOY_FF_WEAK", format!("{:.3}", weak)),
+                    ("M2JOY_FF_LENGTH_MS", data.replay.length.to_string()),
+                    ("M2JOY_FF_COUNT", count.to_string()),
+                ]);
+            }
+            FfCommand::Gain(gain) => {
+                let gain = gain as f32 / u16::MAX as f32;
+                if hook.is_none() {
+                    log::info!("{}Force feedback: gain {:.2}", label, gain);
+                }
+                env.extend([
+                    ("M2JOY_FF_EVENT", "gain".to_string()),
+                    ("M2JOY_FF_GAIN", format!("{:.3}", gain)),
+                ]);
+            }
+        }
+        let Some(hook) = hook else {
+            continue;
+        };
+        // Reap finished runs; a hook still going does not hold up the next
+        children.retain_mut(|c| !matches!(c.try_wait(), Ok(Some(_))));
+        match Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .envs(env)
+            .stdin(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => children.push(child),
+            Err(e) => log::warn!("{}Failed to run force feedback hook: {}", label, e),
+        }
+    }
+}
+
+fn kind_name(data: &FFEffectData) -> &'static str {
+    match data.kind {
+        FFEffectKind::Rumble { .. } => "rumble",
+        FFEffectKind::Periodic { .. } => "periodic",
+        FFEffectKind::Constant { .. } => "constant",
+        FFEffectKind::Ramp { .. } => "ramp",
+        FFEffectKind::Spring { .. } => "spring",
+        FFEffectKind::Friction { .. } => "friction",
+        FFEffectKind::Damper => "damper",
+        FFEffectKind::Inertia => "inertia",
+    }
+}
+
+/// Strong and weak motor strength, 0 to 1, for an effect played on a
+/// two-motor pad.
+fn motors(data: &FFEffectData) -> (f32, f32) {
+    match data.kind {
+        FFEffectKind::Rumble {
+            strong_magnitude,
+            weak_magnitude,
+        } => (
+            strong_magnitude as f32 / u16::MAX as f32,
+            weak_magnitude as f32 / u16::MAX as f32,
+        ),
+        FFEffectKind::Periodic { magnitude, .. }
+        | FFEffectKind::Constant {
+            level: magnitude, ..
+        } => {
+            let m = (magnitude as f32).abs() / i16::MAX as f32;
+            (m.min(1.0), m.min(1.0))
+        }
+        _ => (0.0, 0.0),
+    }
+}
+
+fn describe(data: &FFEffectData) -> String {
+    let what = match data.kind {
+        FFEffectKind::Rumble { .. } => {
+            let (strong, weak) = motors(data);
+            format!("rumble strong {:.2} weak {:.2}", strong, weak)
+        }
+        FFEffectKind::Periodic {
+            waveform, period, ..
+        } => {
+            let wave = match waveform {
+                FFWaveform::Square => "square",
+                FFWaveform::Triangle => "triangle",
+                FFWaveform::Sine => "sine",
+                FFWaveform::SawUp => "saw-up",
+                FFWaveform::SawDown => "saw-down",
+            };
+            format!("{} wave {:.2}, period {} ms", wave, motors(data).0, period)
+        }
+        _ => kind_name(data).to_string(),
+    };
+    match data.replay.length {
+        0 => format!("{}, until stopped", what),
+        ms => format!("{}, {} ms", what, ms),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(sink: FfSink, command: Option<&str>) -> FfConfig {
+        FfConfig {
+            sink,
+            command: command.map(String::from),
+        }
+    }
+
+    #[test]
+    fn command_goes_with_the_hook_sink_only() {
+        assert!(config(FfSink::Hook, Some("notify-send rumble"))
+            .validate()
+            .is_ok());
+        assert!(config(FfSink::Hook, None).validate().is_err());
+        for sink in [FfSink::Gamepad, FfSink::Log, FfSink::Off] {
+            assert!(config(sink, None).validate().is_ok());
+            assert!(config(sink, Some("true")).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn physical_pad_types_are_offered_without_custom() {
+        let mut physical = AttributeSet::new();
+        for t in [
+            FFEffectType::FF_RUMBLE,
+            FFEffectType::FF_CONSTANT,
+            FFEffectType::FF_CUSTOM,
+        ] {
+            physical.insert(t);
+        }
+        let offered = config(FfSink::Gamepad, None)
+            .offered(Some(&physical))
+            .unwrap();
+        assert_eq!(
+            offered.iter().collect::<Vec<_>>(),
+            [FFEffectType::FF_RUMBLE, FFEffectType::FF_CONSTANT]
+        );
+    }
+
+    #[test]
+    fn other_sinks_offer_the_rumble_set() {
+        let mut physical = AttributeSet::new();
+        physical.insert(FFEffectType::FF_CONSTANT);
+        for (sink, pad) in [
+            (FfSink::Gamepad, None),
+            (FfSink::Log, Some(&*physical)),
+            (FfSink::Hook, None),
+        ] {
+            let offered = config(sink, None).offered(pad).unwrap();
+            assert_eq!(offered.iter().collect::<Vec<_>>(), OFFERED);
+        }
+        assert!(config(FfSink::Off, None).offered(Some(&physical)).is_none());
+    }
+}
@@ -0,0 +1,447 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// Smoothing stage between raw mouse deltas and the acceleration curve.
+///
+/// Called once per loop tick with the counts that arrived during that tick
+/// (often zero: a 125Hz mouse only reports every ~8 ticks at 1kHz) and the
+/// measured tick length `dt` in seconds. Returns the smoothed velocity in
+/// counts per second. Every filter is defined in real time, so results do not
+/// depend on the loop rate or on an occasional late tick.
+pub trait Filter {
+    fn update(&mut self, dx: f32, dy: f32, dt: f32) -> (f32, f32);
+
+    /// Drop all history (stick released, mouse idle).
+    fn reset(&mut self);
+}
+
+/// Filter selection and parameters, as written in a profile.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FilterConfig {
+    /// Exponential moving average. New deltas are added at full strength
+    /// (instant response) and decay with `half_life_ms` in between.
+    Ema {
+        #[serde(default = "default_half_life_ms")]
+        half_life_ms: f32,
+    },
+    /// One-Euro filter: low-pass whose cutoff rises with speed, so slow aim
+    /// is heavily smoothed and fast turns get little lag.
+    #[serde(rename = "one-euro")]
+    OneEuro {
+        /// Cutoff at rest, in Hz.
+        #[serde(default = "default_min_cutoff")]
+        min_cutoff: f32,
+        /// Extra cutoff per count/s of speed, in Hz.
+        #[serde(default = "default_beta")]
+        beta: f32,
+        /// Cutoff of the speed estimate that drives the adaptation, in Hz.
+        #[serde(default = "default_d_cutoff")]
+        d_cutoff: f32,
+    },
+    /// Plain average of the counts seen over the last `window_ms`.
+    Average {
+        #[serde(default = "default_window_ms")]
+        window_ms: f32,
+    },
+    /// Constant-velocity Kalman filter on the accumulated position. Tracks
+    /// steady motion between reports instead of decaying toward zero.
+    Kalman {
+        /// Acceleration noise density, in (counts/s²)²·s. Higher follows
+        /// speed changes faster.
+        #[serde(default = "default_process_noise")]
+        process_noise: f32,
+        /// Position measurement noise, in counts².
+        #[serde(default = "default_measurement_noise")]
+        measurement_noise: f32,
+    },
+}
+
+/// EMA half-life. 34ms ≈ a decay of 0.98 per 1ms tick.
+/// Between 125Hz mouse reports (~8ms), decays to 0.5^(8/34) ≈ 0.85 — moderate hold.
+/// Long enough to smooth between reports, short enough to not accumulate wildly.
+fn default_half_life_ms() -> f32 {
+    34.0
+}
+
+/// Matches the EMA's ~3Hz cutoff when the mouse barely moves.
+fn default_min_cutoff() -> f32 {
+    3.0
+}
+
+/// Reaches ~20Hz cutoff at 2000 counts/s.
+fn default_beta() -> f32 {
+    0.0085
+}
+
+fn default_d_cutoff() -> f32 {
+    10.0
+}
+
+/// Covers two reports of a 125Hz mouse.
+fn default_window_ms() -> f32 {
+    16.0
+}
+
+fn default_process_noise() -> f32 {
+    5e7
+}
+
+fn default_measurement_noise() -> f32 {
+    1.0
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig::Ema {
+            half_life_ms: default_half_life_ms(),
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            FilterConfig::Ema { half_life_ms } if half_life_ms <= 0.0 => {
+                Err("ema filter needs half_life_ms > 0".into())
+            }
+            FilterConfig::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } if min_cutoff <= 0.0 || beta < 0.0 || d_cutoff <= 0.0 => {
+                Err("one-euro filter needs min_cutoff > 0, beta >= 0 and d_cutoff > 0".into())
+            }
+            FilterConfig::Average { window_ms } if window_ms <= 0.0 => {
+                Err("average filter needs window_ms > 0".into())
+            }
+            FilterConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } if process_noise <= 0.0 || measurement_noise <= 0.0 => {
+                Err("kalman filter needs positive process_noise and measurement_noise".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Copy with every time constant multiplied by `factor` (cutoff
+    /// frequencies divided by it). The Kalman filter already follows the
+    /// actual report timing and is returned unchanged.
+    pub fn scaled(&self, factor: f32) -> FilterConfig {
+        match self.clone() {
+            FilterConfig::Ema { half_life_ms } => FilterConfig::Ema {
+                half_life_ms: half_life_ms * factor,
+            },
+            FilterConfig::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => FilterConfig::OneEuro {
+                min_cutoff: min_cutoff / factor,
+                beta: beta / factor,
+                d_cutoff: d_cutoff / factor,
+            },
+            FilterConfig::Average { window_ms } => FilterConfig::Average {
+                window_ms: window_ms * factor,
+            },
+            kalman @ FilterConfig::Kalman { .. } => kalman,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Filter> {
+        match *self {
+            FilterConfig::Ema { half_life_ms } => Box::new(Ema {
+                rate: std::f32::consts::LN_2 / (half_life_ms / 1000.0),
+                x: 0.0,
+                y: 0.0,
+            }),
+            FilterConfig::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => Box::new(OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+                speed: (0.0, 0.0),
+                value: (0.0, 0.0),
+            }),
+            FilterConfig::Average { window_ms } => Box::new(Average {
+                window: window_ms / 1000.0,
+                samples: VecDeque::new(),
+                span: 0.0,
+                sum: (0.0, 0.0),
+            }),
+            FilterConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } => Box::new(Kalman {
+                q: process_noise,
+                r: measurement_noise,
+                x: KalmanAxis::default(),
+                y: KalmanAxis::default(),
+            }),
+        }
+    }
+}
+
+struct Ema {
+    /// Decay rate in 1/s (ln 2 / half-life).
+    rate: f32,
+    x: f32,
+    y: f32,
+}
+
+impl Filter for Ema {
+    fn update(&mut self, dx: f32, dy: f32, dt: f32) -> (f32, f32) {
+        let decay = (-self.rate * dt).exp();
+        self.x = self.x * decay + dx;
+        self.y = self.y * decay + dy;
+        // At a steady v counts/s the accumulator settles at v / rate.
+        (self.x * self.rate, self.y * self.rate)
+    }
+
+    fn reset(&mut self) {
+        self.x = 0.0;
+        self.y = 0.0;
+    }
+}
+
+/// Smoothing factor of a first-order low-pass at `cutoff` Hz.
+fn lowpass_alpha(cutoff: f32, dt: f32) -> f32 {
+    let r = 2.0 * std::f32::consts::PI * cutoff * dt;
+    r / (r + 1.0)
+}
+
+/// One-Euro on velocity. Both axes share one cutoff, driven by the speed
+/// magnitude, so diagonal motion is not distorted.
+struct OneEuro {
+    min_cutoff: f32,
+    beta: f32,
+    d_cutoff: f32,
+    speed: (f32, f32),
+    value: (f32, f32),
+}
+
+impl Filter for OneEuro {
+    fn update(&mut self, dx: f32, dy: f32, dt: f32) -> (f32, f32) {
+        let (rx, ry) = (dx / dt, dy / dt);
+
+        let a = lowpass_alpha(self.d_cutoff, dt);
+        self.speed.0 += a * (rx - self.speed.0);
+        self.speed.1 += a * (ry - self.speed.1);
+
+        let cutoff = self.min_cutoff + self.beta * self.speed.0.hypot(self.speed.1);
+        let a = lowpass_alpha(cutoff, dt);
+        self.value.0 += a * (rx - self.value.0);
+        self.value.1 += a * (ry - self.value.1);
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.speed = (0.0, 0.0);
+        self.value = (0.0, 0.0);
+    }
+}
+
+struct Average {
+    /// Window length in seconds.
+    window: f32,
+    /// (dt, dx, dy) for each tick still inside the window.
+    samples: VecDeque<(f32, f32, f32)>,
+    span: f32,
+    sum: (f32, f32),
+}
+
+impl Filter for Average {
+    fn update(&mut self, dx: f32, dy: f32, dt: f32) -> (f32, f32) {
+        self.samples.push_back((dt, dx, dy));
+        self.span += dt;
+        self.sum.0 += dx;
+        self.sum.1 += dy;
+        while let Some(&(odt, ox, oy)) = self.samples.front() {
+            if self.span - odt < self.window {
+                break;
+            }
+            self.samples.pop_front();
+            self.span -= odt;
+            self.sum.0 -= ox;
+            self.sum.1 -= oy;
+        }
+        (self.sum.0 / self.window, self.sum.1 / self.window)
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+        self.span = 0.0;
+        self.sum = (0.0, 0.0);
+    }
+}
+
+/// Two-state (position, velocity) Kalman filter for one axis.
+///
+/// Position is tracked as the residual between the accumulated counts and the
+/// estimate, so it stays small no matter how long the session runs.
+#[derive(Default)]
+struct KalmanAxis {
+    /// Measured position minus estimated position.
+    residual: f32,
+    v: f32,
+    /// Covariance [[p_pp, p_pv], [p_pv, p_vv]].
+    p_pp: f32,
+    p_pv: f32,
+    p_vv: f32,
+}
+
+impl KalmanAxis {
+    fn update(&mut self, delta: f32, dt: f32, q: f32, r: f32) -> f32 {
+        // Predict with constant velocity and white-noise acceleration.
+        self.residual -= self.v * dt;
+        self.p_pp += dt * (2.0 * self.p_pv + dt * self.p_vv) + q * dt * dt * dt / 3.0;
+        self.p_pv += dt * self.p_vv + q * dt * dt / 2.0;
+        self.p_vv += q * dt;
+
+        // Only ticks that carried a report are measurements; in between the
+        // estimate coasts on its velocity instead of seeing a false stop.
+        if delta != 0.0 {
+            self.residual += delta;
+            let s = self.p_pp + r;
+            let (k_p, k_v) = (self.p_pp / s, self.p_pv / s);
+            let innovation = self.residual;
+            self.residual -= k_p * innovation;
+            self.v += k_v * innovation;
+            let (p_pp, p_pv) = (self.p_pp, self.p_pv);
+            self.p_pp -= k_p * p_pp;
+            self.p_pv -= k_p * p_pv;
+            self.p_vv -= k_v * p_pv;
+        }
+        self.v
+    }
+}
+
+struct Kalman {
+    q: f32,
+    r: f32,
+    x: KalmanAxis,
+    y: KalmanAxis,
+}
+
+impl Filter for Kalman {
+    fn update(&mut self, dx: f32, dy: f32, dt: f32) -> (f32, f32) {
+        (
+            self.x.update(dx, dt, self.q, self.r),
+            self.y.update(dy, dt, self.q, self.r),
+        )
+    }
+
+    fn reset(&mut self) {
+        self.x = KalmanAxis::default();
+        self.y = KalmanAxis::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 0.001;
+
+    /// Feed `counts` every `every` ticks for two seconds and return the
+    /// last output.
+    fn settle(config: &FilterConfig, counts: (f32, f32), every: usize) -> (f32, f32) {
+        let mut filter = config.build();
+        let mut out = (0.0, 0.0);
+        for i in 0..2000 {
+            out = if i % every == 0 {
+                filter.update(counts.0, counts.1, TICK)
+            } else {
+                filter.update(0.0, 0.0, TICK)
+            };
+        }
+        out
+    }
+
+    fn near(got: (f32, f32), want: (f32, f32), tolerance: f32) -> bool {
+        (got.0 - want.0).abs() <= want.0.abs() * tolerance
+            && (got.1 - want.1).abs() <= want.1.abs() * tolerance
+    }
+
+    fn one_euro() -> FilterConfig {
+        FilterConfig::OneEuro {
+            min_cutoff: default_min_cutoff(),
+            beta: default_beta(),
+            d_cutoff: default_d_cutoff(),
+        }
+    }
+
+    fn kalman() -> FilterConfig {
+        FilterConfig::Kalman {
+            process_noise: default_process_noise(),
+            measurement_noise: default_measurement_noise(),
+        }
+    }
+
+    #[test]
+    fn filters_converge_to_a_constant_velocity() {
+        let average = FilterConfig::Average {
+            window_ms: default_window_ms(),
+        };
+        for config in [FilterConfig::default(), one_euro(), average, kalman()] {
+            let v = settle(&config, (2.0, -1.0), 1);
+            // The EMA adds each tick at full strength, ~rate * dt / 2 high
+            assert!(near(v, (2000.0, -1000.0), 0.015), "{:?}: {:?}", config, v);
+        }
+    }
+
+    #[test]
+    fn kalman_tracks_sparse_reports() {
+        // A 125Hz mouse: 16 counts every 8ms is 2000 counts/s
+        let v = settle(&kalman(), (16.0, 8.0), 8);
+        assert!(near(v, (2000.0, 1000.0), 0.05), "{:?}", v);
+    }
+
+    #[test]
+    fn reset_forgets_history() {
+        for config in [FilterConfig::default(), one_euro(), kalman()] {
+            let mut filter = config.build();
+            for _ in 0..100 {
+                filter.update(5.0, 5.0, TICK);
+            }
+            filter.reset();
+            assert_eq!(filter.update(0.0, 0.0, TICK), (0.0, 0.0), "{:?}", config);
+        }
+    }
+
+    /// Outputs at 40, 500, 510 and 540ms of 2000 counts/s for half a second,
+    /// fed as the counts of each tick at `rate` Hz.
+    fn run_at(config: &FilterConfig, rate: u32) -> Vec<f32> {
+        let mut filter = config.build();
+        let dt = 1.0 / rate as f32;
+        let mut out = Vec::new();
+        for tick in 1..=rate * 540 / 1000 {
+            let ms = tick * 1000 / rate;
+            let speed = if tick * 2 <= rate { 2000.0 } else { 0.0 };
+            let (x, _) = filter.update(speed * dt, 0.0, dt);
+            if tick * 1000 % rate == 0 && [40, 500, 510, 540].contains(&ms) {
+                out.push(x);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn results_do_not_depend_on_the_tick_rate() {
+        let average = FilterConfig::Average {
+            window_ms: default_window_ms(),
+        };
+        for config in [FilterConfig::default(), one_euro(), average, kalman()] {
+            let (slow, fast) = (run_at(&config, 1000), run_at(&config, 2000));
+            assert_eq!(slow.len(), 4);
+            // Within 2% of the speed, at the start, the end and the stop
+            for (a, b) in slow.iter().zip(&fast) {
+                assert!((a - b).abs() < 40.0, "{:?}: {:?} {:?}", config, slow, fast);
+            }
+        }
+    }
+}
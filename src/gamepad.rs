@@ -0,0 +1,325 @@
+use crate::device_match::DeviceSelector;
+use crate::identity::Preset;
+use crate::profile::{PadButton, Stick};
+use crate::virtual_pad::PadState;
+use evdev::{AbsoluteAxisType, Device, Key};
+use serde::Deserialize;
+
+/// A physical gamepad merged into the virtual pad.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadConfig {
+    /// Event node path or match rule; auto-detected when absent.
+    pub device: Option<DeviceSelector>,
+    /// How the mouse's stick combines with the same stick on the pad.
+    pub merge: Merge,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Merge {
+    /// The mouse while it moves, the pad's stick otherwise.
+    #[default]
+    MouseWins,
+    /// Both added together, up to full deflection.
+    Sum,
+}
+
+impl Merge {
+    /// Combine the mouse's stick (already shaped) with the pad's.
+    pub fn apply(self, mouse: (f32, f32), pad: (f32, f32)) -> (f32, f32) {
+        match self {
+            Merge::MouseWins if mouse != (0.0, 0.0) => mouse,
+            Merge::MouseWins => pad,
+            Merge::Sum => {
+                let (x, y) = (mouse.0 + pad.0, mouse.1 + pad.1);
+                // Pushed past the rim, the stick stays on it
+                let len = x.hypot(y);
+                if len > 1.0 {
+                    (x / len, y / len)
+                } else {
+                    (x, y)
+                }
+            }
+        }
+    }
+}
+
+/// The pad's analog controls at one report, normalized: sticks -1 to 1,
+/// triggers 0 to 1, hat -1, 0 or 1 per axis.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PadAxes {
+    pub sticks: [(f32, f32); 2],
+    pub triggers: [f32; 2],
+    pub hat: (i32, i32),
+}
+
+/// Range and flat zone of one absolute axis.
+#[derive(Debug, Clone, Copy, Default)]
+struct Range {
+    min: i32,
+    max: i32,
+    flat: i32,
+}
+
+/// Ranges of the axes `PadAxes` reads, for normalizing raw values. Pads
+/// differ: 0-255 sticks and triggers on some, ±32768 and 0-1023 on others.
+#[derive(Debug, Clone, Default)]
+pub struct AxisRanges {
+    ranges: [Range; 8],
+}
+
+const AXES: [AbsoluteAxisType; 8] = [
+    AbsoluteAxisType::ABS_X,
+    AbsoluteAxisType::ABS_Y,
+    AbsoluteAxisType::ABS_RX,
+    AbsoluteAxisType::ABS_RY,
+    AbsoluteAxisType::ABS_Z,
+    AbsoluteAxisType::ABS_RZ,
+    AbsoluteAxisType::ABS_HAT0X,
+    AbsoluteAxisType::ABS_HAT0Y,
+];
+
+impl AxisRanges {
+    pub fn of(device: &Device) -> Self {
+        let mut ranges = [Range::default(); 8];
+        if let Ok(state) = device.get_abs_state() {
+            for (range, axis) in ranges.iter_mut().zip(AXES) {
+                let info = state[axis.0 as usize];
+                *range = Range {
+                    min: info.minimum,
+                    max: info.maximum,
+                    flat: info.flat,
+                };
+            }
+        }
+        Self { ranges }
+    }
+
+    /// The device's current position of every axis, e.g. right after a grab.
+    pub fn current(&self, device: &Device) -> PadAxes {
+        let mut axes = PadAxes::default();
+        if let Ok(state) = device.get_abs_state() {
+            for axis in AXES {
+                self.set(&mut axes, axis, state[axis.0 as usize].value);
+            }
+        }
+        axes
+    }
+
+    /// Store a raw axis value. Returns false for axes the pad does not
+    /// forward.
+    pub fn set(&self, axes: &mut PadAxes, axis: AbsoluteAxisType, value: i32) -> bool {
+        let Some(i) = AXES.iter().position(|&a| a == axis) else {
+            return false;
+        };
+        let Range { min, max, flat } = self.ranges[i];
+        if max <= min {
+            return false;
+        }
+        let span = (max - min) as f32;
+        match i {
+            0..=3 => {
+                let center = (min + max) as f32 / 2.0;
+                let v = value as f32 - center;
+                let v = if v.abs() <= flat as f32 {
+                    0.0
+                } else {
+                    (2.0 * v / span).clamp(-1.0, 1.0)
+                };
+                let stick = &mut axes.sticks[i / 2];
+                if i % 2 == 0 {
+                    stick.0 = v;
+                } else {
+                    stick.1 = v;
+                }
+            }
+            4 | 5 => axes.triggers[i - 4] = ((value - min) as f32 / span).clamp(0.0, 1.0),
+            6 => axes.hat.0 = value.signum(),
+            _ => axes.hat.1 = value.signum(),
+        }
+        true
+    }
+}
+
+/// Whether a device has gamepad buttons and a stick.
+pub fn is_gamepad(device: &Device) -> bool {
+    let stick = device.supported_absolute_axes().is_some_and(|axes| {
+        axes.contains(AbsoluteAxisType::ABS_X) && axes.contains(AbsoluteAxisType::ABS_Y)
+    });
+    stick
+        && device
+            .supported_keys()
+            .is_some_and(|keys| keys.contains(Key::BTN_SOUTH))
+}
+
+/// The virtual pad's button for a key of the physical one. Keys the
+/// virtual pad has come out as the same code, even where its preset
+/// departs from the standard layout (the Xbox 360 pad's swapped X and Y).
+pub fn pad_button(key: Key, preset: Preset) -> Option<PadButton> {
+    let find = |code: fn(PadButton) -> Option<Key>| {
+        PadButton::ALL.into_iter().find(|&b| code(b) == Some(key))
+    };
+    match key {
+        Key::BTN_DPAD_UP => Some(PadButton::Up),
+        Key::BTN_DPAD_DOWN => Some(PadButton::Down),
+        Key::BTN_DPAD_LEFT => Some(PadButton::Left),
+        Key::BTN_DPAD_RIGHT => Some(PadButton::Right),
+        _ => PadButton::ALL
+            .into_iter()
+            .find(|&b| preset.key(b) == Some(key))
+            .or_else(|| find(PadButton::key)),
+    }
+}
+
+/// What the physical pad holds, kept up to date from its reader.
+#[derive(Debug, Default)]
+pub struct Physical {
+    /// One bit per `PadButton`.
+    pub buttons: u32,
+    pub axes: PadAxes,
+}
+
+impl Physical {
+    /// Buttons, D-pad hat and analog triggers as a `PadState`.
+    pub fn pad(&self) -> PadState {
+        let mut pad = PadState {
+            buttons: self.buttons,
+            triggers: self.axes.triggers,
+        };
+        let (x, y) = self.axes.hat;
+        for (on, button) in [
+            (x < 0, PadButton::Left),
+            (x > 0, PadButton::Right),
+            (y < 0, PadButton::Up),
+            (y > 0, PadButton::Down),
+        ] {
+            if on {
+                pad.press(button);
+            }
+        }
+        pad
+    }
+
+    pub fn stick(&self, stick: Stick) -> (f32, f32) {
+        self.axes.sticks[stick as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The kernel's names for the codes xpad reports
+    const BTN_A: Key = Key::BTN_SOUTH;
+    const BTN_B: Key = Key::BTN_EAST;
+    const BTN_X: Key = Key::BTN_NORTH;
+    const BTN_Y: Key = Key::BTN_WEST;
+
+    #[test]
+    fn xpad_face_buttons_pass_through_the_xbox_360_preset() {
+        let preset = Preset::Xbox360;
+        // xpad: X (left) is BTN_X, Y (top) is BTN_Y
+        for key in [BTN_X, BTN_Y, BTN_A, BTN_B] {
+            let button = pad_button(key, preset).unwrap();
+            assert_eq!(preset.key(button), Some(key));
+        }
+        assert_eq!(pad_button(BTN_X, preset), Some(PadButton::West));
+        assert_eq!(pad_button(BTN_Y, preset), Some(PadButton::North));
+    }
+
+    #[test]
+    fn standard_layout_is_positional() {
+        assert_eq!(
+            pad_button(Key::BTN_NORTH, Preset::M2joy),
+            Some(PadButton::North)
+        );
+        assert_eq!(
+            pad_button(Key::BTN_DPAD_LEFT, Preset::Ds4),
+            Some(PadButton::Left)
+        );
+    }
+
+    #[test]
+    fn keys_the_preset_lacks_fall_back_to_the_standard_layout() {
+        // The Xbox 360 pad has no digital L2; a DS4's press still counts
+        assert_eq!(
+            pad_button(Key::BTN_TL2, Preset::Xbox360),
+            Some(PadButton::L2)
+        );
+        assert_eq!(pad_button(Key::BTN_Z, Preset::M2joy), None);
+    }
+
+    #[test]
+    fn mouse_wins_only_while_it_moves() {
+        let pad = (0.0, -1.0);
+        assert_eq!(Merge::MouseWins.apply((0.3, 0.0), pad), (0.3, 0.0));
+        assert_eq!(Merge::MouseWins.apply((0.0, 0.0), pad), pad);
+    }
+
+    #[test]
+    fn sum_stays_inside_the_rim() {
+        assert_eq!(Merge::Sum.apply((0.25, 0.0), (0.0, -0.5)), (0.25, -0.5));
+        let (x, y) = Merge::Sum.apply((1.0, 0.0), (0.0, 1.0));
+        assert!((x - 0.5f32.sqrt()).abs() < 1e-6 && (y - 0.5f32.sqrt()).abs() < 1e-6);
+        assert_eq!(Merge::Sum.apply((0.8, 0.0), (0.8, 0.0)), (1.0, 0.0));
+    }
+
+    /// Every stick axis with one range, triggers 0-255 and a -1 to 1 hat.
+    fn ranges(min: i32, max: i32, flat: i32) -> AxisRanges {
+        let stick = Range { min, max, flat };
+        let trigger = Range {
+            min: 0,
+            max: 255,
+            flat: 0,
+        };
+        let hat = Range {
+            min: -1,
+            max: 1,
+            flat: 0,
+        };
+        AxisRanges {
+            ranges: [stick, stick, stick, stick, trigger, trigger, hat, hat],
+        }
+    }
+
+    fn stick(ranges: &AxisRanges, value: i32) -> f32 {
+        let mut axes = PadAxes::default();
+        assert!(ranges.set(&mut axes, AbsoluteAxisType::ABS_RX, value));
+        axes.sticks[1].0
+    }
+
+    #[test]
+    fn sticks_normalize_around_their_centre() {
+        let wide = ranges(-32768, 32767, 0);
+        assert_eq!(stick(&wide, -32768), -1.0);
+        assert_eq!(stick(&wide, 32767), 1.0);
+        assert!((stick(&wide, 16384) - 0.5).abs() < 1e-4);
+        let byte = ranges(0, 255, 0);
+        assert_eq!(stick(&byte, 0), -1.0);
+        assert_eq!(stick(&byte, 255), 1.0);
+        assert!((stick(&byte, 64) + 0.498).abs() < 1e-3);
+    }
+
+    #[test]
+    fn flat_zone_reads_as_centred() {
+        let ranges = ranges(-32768, 32767, 500);
+        assert_eq!(stick(&ranges, 499), 0.0);
+        assert_eq!(stick(&ranges, -500), 0.0);
+        assert!(stick(&ranges, 600) > 0.0);
+    }
+
+    #[test]
+    fn triggers_hat_and_other_axes() {
+        let ranges = ranges(0, 255, 0);
+        let mut axes = PadAxes::default();
+        assert!(ranges.set(&mut axes, AbsoluteAxisType::ABS_RZ, 255));
+        assert!(ranges.set(&mut axes, AbsoluteAxisType::ABS_HAT0Y, -1));
+        assert_eq!(axes.triggers, [0.0, 1.0]);
+        assert_eq!(axes.hat, (0, -1));
+        assert!(!ranges.set(&mut axes, AbsoluteAxisType::ABS_THROTTLE, 10));
+        // Axes the device lacks have no range
+        let none = AxisRanges::default();
+        assert!(!none.set(&mut axes, AbsoluteAxisType::ABS_X, 10));
+    }
+}
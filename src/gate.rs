@@ -0,0 +1,127 @@
+use serde::Deserialize;
+
+/// Shape of the physical stick gate that limits the final output.
+///
+/// Coordinates are normalized so 1.0 is full deflection on one axis.
+/// Applied after deadzone compensation, before the ±32767 clamp.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Gate {
+    /// Each axis clamped on its own (the raw uinput range). Diagonals reach
+    /// the corners, and fast flicks bend toward them.
+    #[default]
+    Square,
+    /// Radial clamp at full deflection; direction is always preserved.
+    Circle,
+    /// Octagon with vertices at `cardinal` on the axes and at
+    /// (`diagonal`, `diagonal`) on the 45° lines.
+    Octagon { cardinal: f32, diagonal: f32 },
+    /// N64 controller: ~±80 on the axes, ~(70, 70) on the diagonals. Assumes
+    /// the core maps full stick to ±80 (Mupen64Plus/ParaLLEl defaults).
+    N64,
+    /// GameCube controller: regular octagon, as Dolphin's default gate.
+    Gamecube,
+}
+
+/// Diagonal vertex of the N64 gate relative to its cardinal reach (70/80).
+const N64_DIAGONAL: f32 = 0.875;
+
+impl Gate {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Gate::Octagon { cardinal, diagonal } = *self {
+            if cardinal <= 0.0 || cardinal > 1.0 || diagonal <= 0.0 || diagonal > 1.0 {
+                return Err("octagon gate needs cardinal and diagonal in (0, 1]".into());
+            }
+            // Keep the shape convex: the diagonal vertex must lie outside the
+            // cardinal-to-cardinal chord but not past the square's corner.
+            if diagonal * 2.0 <= cardinal || diagonal > cardinal {
+                return Err("octagon gate needs cardinal / 2 < diagonal <= cardinal".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Limit a normalized (x, y) vector to the gate. Every shape except
+    /// `Square` keeps the vector's direction.
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let (cardinal, diagonal) = match *self {
+            Gate::Square => return (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0)),
+            Gate::Circle => {
+                let mag = x.hypot(y);
+                return if mag > 1.0 {
+                    (x / mag, y / mag)
+                } else {
+                    (x, y)
+                };
+            }
+            Gate::Octagon { cardinal, diagonal } => (cardinal, diagonal),
+            Gate::N64 => (1.0, N64_DIAGONAL),
+            Gate::Gamecube => (1.0, std::f32::consts::FRAC_1_SQRT_2),
+        };
+
+        let mag = x.hypot(y);
+        if mag == 0.0 {
+            return (0.0, 0.0);
+        }
+        // Fold into the first octant (0°-45°), where the edge runs from
+        // A = (cardinal, 0) to B = (diagonal, diagonal).
+        let (u, v) = {
+            let (ax, ay) = (x.abs(), y.abs());
+            if ay > ax {
+                (ay / mag, ax / mag)
+            } else {
+                (ax / mag, ay / mag)
+            }
+        };
+        let (ex, ey) = (diagonal - cardinal, diagonal);
+        // Ray t*(u, v) meets the edge where cross(t*d - A, E) = 0.
+        let reach = cardinal * ey / (u * ey - v * ex);
+        if mag > reach {
+            let k = reach / mag;
+            (x * k, y * k)
+        } else {
+            (x, y)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5
+    }
+
+    #[test]
+    fn square_clamps_each_axis() {
+        assert_eq!(Gate::Square.apply(1.5, 1.5), (1.0, 1.0));
+        // A fast flick bends toward the corner
+        assert_eq!(Gate::Square.apply(2.0, 0.5), (1.0, 0.5));
+        assert_eq!(Gate::Square.apply(-0.5, 0.3), (-0.5, 0.3));
+    }
+
+    #[test]
+    fn circle_clamps_the_radius() {
+        let d = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(close(Gate::Circle.apply(1.0, 1.0), (d, d)));
+        assert!(close(
+            Gate::Circle.apply(2.0, 0.5),
+            (0.970_142_5, 0.242_535_6)
+        ));
+        assert_eq!(Gate::Circle.apply(-0.5, 0.3), (-0.5, 0.3));
+    }
+
+    #[test]
+    fn octagon_reaches_its_vertices() {
+        let gate = Gate::Octagon {
+            cardinal: 0.9,
+            diagonal: 0.7,
+        };
+        assert!(close(gate.apply(2.0, 0.0), (0.9, 0.0)));
+        assert!(close(gate.apply(0.0, -2.0), (0.0, -0.9)));
+        assert!(close(gate.apply(-1.0, 1.0), (-0.7, 0.7)));
+        assert!(close(Gate::N64.apply(1.0, 1.0), (0.875, 0.875)));
+        assert_eq!(gate.apply(0.0, 0.0), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,467 @@
+use crate::mapping::Input;
+use crate::mouse::ButtonEvent;
+use evdev::Key;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Timing thresholds for tap, hold, double-click and chord bindings.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct GestureConfig {
+    /// Held at least this long is a hold.
+    pub hold_ms: f32,
+    /// A second press within this long after a tap is a double-click.
+    pub double_ms: f32,
+    /// Two buttons pressed within this long of each other are a chord.
+    pub chord_ms: f32,
+    /// Shortest press sent for a tap, double-click or chord, so a quick
+    /// click still lasts a few frames.
+    pub tap_ms: f32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            hold_ms: 300.0,
+            double_ms: 250.0,
+            chord_ms: 50.0,
+            tap_ms: 50.0,
+        }
+    }
+}
+
+impl GestureConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if [self.hold_ms, self.double_ms, self.chord_ms, self.tap_ms]
+            .iter()
+            .any(|&ms| ms <= 0.0)
+        {
+            return Err("gestures hold_ms, double_ms, chord_ms and tap_ms must be > 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// What a button's bindings make it wait for.
+#[derive(Debug, Default)]
+struct KeyGestures {
+    /// Has tap, hold or double bindings: short presses come out as `Tap`.
+    gestured: bool,
+    hold: bool,
+    double: bool,
+    /// Buttons it forms a chord with.
+    partners: Vec<Key>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    /// Pressed; a chord partner may still follow.
+    ChordWait { since: Duration },
+    /// Pressed; not yet known whether tap or hold.
+    Pressed { since: Duration },
+    /// Tapped; a second press soon is a double-click.
+    TapWait { since: Duration },
+    /// Sending `input` until released.
+    Active { input: Input, since: Duration },
+    /// In a chord; whichever of the two lets go first ends it.
+    Chord { partner: Key, since: Duration },
+    /// The partner ended our chord; only the release is left.
+    Spent,
+}
+
+/// Turns presses of buttons with tap/hold/double/chord bindings into
+/// presses of those inputs, once their timing is known.
+///
+/// Buttons without such bindings never pass through here, so they keep
+/// zero latency. A tap comes out on release when the button also has a
+/// hold binding, `double_ms` after release with a double binding, and
+/// chord members wait at most `chord_ms` for their partner. A button with
+/// a double but no hold binding held past `double_ms` is a plain press.
+pub struct Gestures {
+    hold: Duration,
+    double: Duration,
+    chord: Duration,
+    tap: Duration,
+    keys: HashMap<Key, KeyGestures>,
+    phases: HashMap<Key, Phase>,
+    /// Mice holding each button; only the first press and last release
+    /// count.
+    down: HashMap<Key, u32>,
+    /// Releases held back so short presses last `tap_ms`.
+    releases: Vec<(Duration, Input)>,
+}
+
+impl Gestures {
+    /// Detection for every button some of `inputs` need it for.
+    pub fn new(inputs: impl Iterator<Item = Input>, config: &GestureConfig) -> Self {
+        let mut keys: HashMap<Key, KeyGestures> = HashMap::new();
+        for input in inputs {
+            match input {
+                Input::Tap(k) => keys.entry(k).or_default().gestured = true,
+                Input::Hold(k) => {
+                    let g = keys.entry(k).or_default();
+                    g.gestured = true;
+                    g.hold = true;
+                }
+                Input::Double(k) => {
+                    let g = keys.entry(k).or_default();
+                    g.gestured = true;
+                    g.double = true;
+                }
+                Input::Chord(a, b) => {
+                    for (key, partner) in [(a, b), (b, a)] {
+                        let partners = &mut keys.entry(key).or_default().partners;
+                        if !partners.contains(&partner) {
+                            partners.push(partner);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let ms = |v: f32| Duration::from_secs_f32(v / 1000.0);
+        Self {
+            hold: ms(config.hold_ms),
+            double: ms(config.double_ms),
+            chord: ms(config.chord_ms),
+            tap: ms(config.tap_ms),
+            keys,
+            phases: HashMap::new(),
+            down: HashMap::new(),
+            releases: Vec::new(),
+        }
+    }
+
+    /// Whether presses of this button go through gesture detection.
+    pub fn handles(&self, key: Key) -> bool {
+        self.keys.contains_key(&key)
+    }
+
+    /// Whether a press of this button comes out as `Tap` rather than as
+    /// the button itself.
+    pub fn gestured(&self, key: Key) -> bool {
+        self.keys.get(&key).is_some_and(|g| g.gestured)
+    }
+
+    /// Take a press or release of a handled button, adding the resulting
+    /// events to `out`.
+    pub fn feed(&mut self, event: &ButtonEvent, out: &mut Vec<ButtonEvent>) {
+        let Input::Key(key) = event.input else {
+            return;
+        };
+        // Timeouts up to now come first
+        self.poll(event.time, out);
+
+        let down = self.down.entry(key).or_default();
+        if event.value != 0 {
+            *down += 1;
+            if *down == 1 {
+                self.press(key, event.time, out);
+            }
+        } else if *down > 0 {
+            *down -= 1;
+            if *down == 0 {
+                self.release(key, event.time, out);
+            }
+        }
+    }
+
+    fn press(&mut self, key: Key, t: Duration, out: &mut Vec<ButtonEvent>) {
+        if let Some(Phase::TapWait { .. }) = self.phases.get(&key) {
+            self.activate(key, Input::Double(key), t, out);
+            return;
+        }
+        let partners = &self.keys[&key].partners;
+        let waiting = partners.iter().copied().find(|p| {
+            matches!(self.phases.get(p), Some(Phase::ChordWait { since }) if t - *since <= self.chord)
+        });
+        if let Some(partner) = waiting {
+            self.phases.insert(
+                partner,
+                Phase::Chord {
+                    partner: key,
+                    since: t,
+                },
+            );
+            self.phases.insert(key, Phase::Chord { partner, since: t });
+            out.push(press(chord(key, partner), t));
+        } else if !partners.is_empty() {
+            self.phases.insert(key, Phase::ChordWait { since: t });
+        } else {
+            self.start(key, t, t, out);
+        }
+    }
+
+    /// Begin tap/hold detection for a press at `since`, decided at `at`.
+    fn start(&mut self, key: Key, since: Duration, at: Duration, out: &mut Vec<ButtonEvent>) {
+        let g = &self.keys[&key];
+        if g.hold || g.double {
+            self.phases.insert(key, Phase::Pressed { since });
+        } else {
+            let input = if g.gestured {
+                Input::Tap(key)
+            } else {
+                Input::Key(key)
+            };
+            self.activate(key, input, at, out);
+        }
+    }
+
+    fn activate(&mut self, key: Key, input: Input, t: Duration, out: &mut Vec<ButtonEvent>) {
+        self.phases.insert(key, Phase::Active { input, since: t });
+        out.push(press(input, t));
+    }
+
+    fn release(&mut self, key: Key, t: Duration, out: &mut Vec<ButtonEvent>) {
+        // A short press that no partner joined: run it as a plain press
+        if let Some(&Phase::ChordWait { since }) = self.phases.get(&key) {
+            self.start(key, since, t, out);
+        }
+        match self.phases.remove(&key) {
+            Some(Phase::Pressed { .. }) if self.keys[&key].double => {
+                self.phases.insert(key, Phase::TapWait { since: t });
+            }
+            Some(Phase::Pressed { .. }) => self.tap(key, t, out),
+            Some(Phase::Active { input, since }) => self.release_after(input, since, t, out),
+            Some(Phase::Chord { partner, since }) => {
+                self.phases.insert(partner, Phase::Spent);
+                self.release_after(chord(key, partner), since, t, out);
+            }
+            _ => {}
+        }
+    }
+
+    /// A tap: press now, release `tap_ms` later.
+    fn tap(&mut self, key: Key, t: Duration, out: &mut Vec<ButtonEvent>) {
+        out.push(press(Input::Tap(key), t));
+        self.releases.push((t + self.tap, Input::Tap(key)));
+    }
+
+    /// Release `input` at `t`, or once it has lasted `tap_ms`.
+    fn release_after(
+        &mut self,
+        input: Input,
+        since: Duration,
+        t: Duration,
+        out: &mut Vec<ButtonEvent>,
+    ) {
+        let end = since + self.tap;
+        if end > t {
+            self.releases.push((end, input));
+        } else {
+            out.push(release(input, t));
+        }
+    }
+
+    /// Settle everything whose time has run out by `now`.
+    pub fn poll(&mut self, now: Duration, out: &mut Vec<ButtonEvent>) {
+        // One step per round: a chord wait running out can start a press
+        // that is already past its hold time.
+        loop {
+            let mut due: Vec<(Key, Phase)> = self
+                .phases
+                .iter()
+                .filter(|(key, phase)| match **phase {
+                    Phase::ChordWait { since } => now >= since + self.chord,
+                    Phase::Pressed { since } => now >= since + self.press_limit(**key),
+                    Phase::TapWait { since } => now >= since + self.double,
+                    _ => false,
+                })
+                .map(|(&k, &p)| (k, p))
+                .collect();
+            if due.is_empty() {
+                break;
+            }
+            due.sort_by_key(|(k, _)| *k);
+            for (key, phase) in due {
+                match phase {
+                    Phase::ChordWait { since } => self.start(key, since, since + self.chord, out),
+                    Phase::Pressed { since } => {
+                        let input = if self.keys[&key].hold {
+                            Input::Hold(key)
+                        } else {
+                            Input::Key(key)
+                        };
+                        self.activate(key, input, since + self.press_limit(key), out)
+                    }
+                    Phase::TapWait { since } => {
+                        self.phases.remove(&key);
+                        self.tap(key, since + self.double, out);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.releases.sort_by_key(|(t, _)| *t);
+        while let Some(&(t, input)) = self.releases.first() {
+            if t > now {
+                break;
+            }
+            self.releases.remove(0);
+            out.push(release(input, t));
+        }
+    }
+
+    /// How long a press stays undecided: up to a hold, or without a hold
+    /// binding, until it is too long to be the first click of a double.
+    fn press_limit(&self, key: Key) -> Duration {
+        if self.keys[&key].hold {
+            self.hold
+        } else {
+            self.double
+        }
+    }
+
+    /// Forget every press in progress, e.g. when the mouse is released.
+    pub fn reset(&mut self) {
+        self.phases.clear();
+        self.down.clear();
+        self.releases.clear();
+    }
+}
+
+fn chord(a: Key, b: Key) -> Input {
+    Input::Chord(a.min(b), a.max(b))
+}
+
+fn press(input: Input, time: Duration) -> ButtonEvent {
+    ButtonEvent {
+        time,
+        input,
+        value: 1,
+    }
+}
+
+fn release(input: Input, time: Duration) -> ButtonEvent {
+    ButtonEvent {
+        time,
+        input,
+        value: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: Key = Key::BTN_LEFT;
+    const RIGHT: Key = Key::BTN_RIGHT;
+
+    fn gestures(inputs: &[Input]) -> Gestures {
+        Gestures::new(inputs.iter().copied(), &GestureConfig::default())
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Feed (ms, key, value) events, polling up to `end` ms, and return the
+    /// output as (ms, input, value).
+    fn run(g: &mut Gestures, events: &[(u64, Key, i32)], end: u64) -> Vec<(u64, Input, i32)> {
+        let mut out = Vec::new();
+        for &(t, key, value) in events {
+            let event = ButtonEvent {
+                time: ms(t),
+                input: Input::Key(key),
+                value,
+            };
+            g.feed(&event, &mut out);
+        }
+        g.poll(ms(end), &mut out);
+        out.iter()
+            .map(|e| (e.time.as_millis() as u64, e.input, e.value))
+            .collect()
+    }
+
+    #[test]
+    fn tap_without_hold_is_sent_at_once() {
+        let mut g = gestures(&[Input::Tap(LEFT)]);
+        let out = run(&mut g, &[(0, LEFT, 1), (10, LEFT, 0)], 100);
+        // Lasts tap_ms even though released sooner
+        assert_eq!(out, [(0, Input::Tap(LEFT), 1), (50, Input::Tap(LEFT), 0)]);
+    }
+
+    #[test]
+    fn short_press_with_hold_binding_is_a_tap_on_release() {
+        let mut g = gestures(&[Input::Hold(LEFT)]);
+        let out = run(&mut g, &[(0, LEFT, 1), (100, LEFT, 0)], 200);
+        assert_eq!(
+            out,
+            [(100, Input::Tap(LEFT), 1), (150, Input::Tap(LEFT), 0)]
+        );
+    }
+
+    #[test]
+    fn hold_starts_after_hold_ms() {
+        let mut g = gestures(&[Input::Hold(LEFT)]);
+        assert!(run(&mut g, &[(0, LEFT, 1)], 299).is_empty());
+        let out = run(&mut g, &[(500, LEFT, 0)], 500);
+        assert_eq!(
+            out,
+            [(300, Input::Hold(LEFT), 1), (500, Input::Hold(LEFT), 0)]
+        );
+    }
+
+    #[test]
+    fn second_press_within_double_ms_is_a_double() {
+        let mut g = gestures(&[Input::Double(LEFT)]);
+        let out = run(
+            &mut g,
+            &[(0, LEFT, 1), (50, LEFT, 0), (200, LEFT, 1), (400, LEFT, 0)],
+            400,
+        );
+        assert_eq!(
+            out,
+            [(200, Input::Double(LEFT), 1), (400, Input::Double(LEFT), 0)]
+        );
+    }
+
+    #[test]
+    fn single_click_with_double_binding_taps_after_double_ms() {
+        let mut g = gestures(&[Input::Double(LEFT)]);
+        let out = run(&mut g, &[(0, LEFT, 1), (50, LEFT, 0)], 400);
+        assert_eq!(
+            out,
+            [(300, Input::Tap(LEFT), 1), (350, Input::Tap(LEFT), 0)]
+        );
+    }
+
+    #[test]
+    fn long_press_with_only_double_binding_is_plain() {
+        let mut g = gestures(&[Input::Double(LEFT)]);
+        let out = run(&mut g, &[(0, LEFT, 1)], 1000);
+        assert_eq!(out, [(250, Input::Key(LEFT), 1)]);
+        let out = run(&mut g, &[(1000, LEFT, 0)], 1000);
+        assert_eq!(out, [(1000, Input::Key(LEFT), 0)]);
+    }
+
+    #[test]
+    fn presses_within_chord_ms_are_a_chord() {
+        let mut g = gestures(&[Input::Chord(LEFT, RIGHT)]);
+        let out = run(
+            &mut g,
+            &[
+                (0, LEFT, 1),
+                (30, RIGHT, 1),
+                (200, RIGHT, 0),
+                (250, LEFT, 0),
+            ],
+            300,
+        );
+        let chord = Input::Chord(LEFT, RIGHT);
+        // Whichever lets go first ends it; the other's release is swallowed
+        assert_eq!(out, [(30, chord, 1), (200, chord, 0)]);
+    }
+
+    #[test]
+    fn presses_further_apart_are_separate() {
+        let mut g = gestures(&[Input::Chord(LEFT, RIGHT)]);
+        let out = run(&mut g, &[(0, LEFT, 1), (80, RIGHT, 1)], 200);
+        assert_eq!(
+            out,
+            [(50, Input::Key(LEFT), 1), (130, Input::Key(RIGHT), 1)]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::ffi::{CStr, CString};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const INPUT_DIR: &str = "/dev/input";
+
+/// Watches /dev/input for new or re-permissioned event nodes (inotify).
+///
+/// udev creates the node first and fixes its group/mode a moment later, so
+/// IN_ATTRIB is watched too: a node that could not be opened on IN_CREATE is
+/// reported again once it becomes readable.
+pub struct DeviceWatcher {
+    fd: OwnedFd,
+}
+
+impl DeviceWatcher {
+    pub fn new() -> std::io::Result<Self> {
+        let raw = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if raw < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+        let dir = CString::new(INPUT_DIR).expect("static path");
+        let mask = libc::IN_CREATE | libc::IN_ATTRIB;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    /// Wait up to `timeout` for changes. Returns the event nodes that were
+    /// created or changed (empty on timeout or interruption).
+    pub fn wait(&self, timeout: Duration) -> std::io::Result<Vec<PathBuf>> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ret = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(Vec::new());
+            }
+            return Err(e);
+        }
+        if ret == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = [0u8; 4096];
+        let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            return match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+
+        let mut paths = Vec::new();
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header <= n as usize {
+            // SAFETY: the kernel writes whole inotify_event records; read_unaligned
+            // because the byte buffer has no alignment guarantee.
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+            let name_start = offset + header;
+            let name_end = name_start + event.len as usize;
+            if event.len > 0 && name_end <= n as usize {
+                if let Ok(name) = CStr::from_bytes_until_nul(&buf[name_start..name_end]) {
+                    let name = name.to_string_lossy();
+                    if name.starts_with("event") {
+                        let path = PathBuf::from(INPUT_DIR).join(name.as_ref());
+                        if !paths.contains(&path) {
+                            paths.push(path);
+                        }
+                    }
+                }
+            }
+            offset = name_end;
+        }
+        Ok(paths)
+    }
+}
+
+/// All current /dev/input/event* nodes, in numeric order.
+pub fn event_nodes() -> Vec<PathBuf> {
+    let mut nodes: Vec<(u32, PathBuf)> = std::fs::read_dir(INPUT_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let n = name.to_str()?.strip_prefix("event")?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(_, p)| p).collect()
+}
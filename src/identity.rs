@@ -0,0 +1,265 @@
+use crate::profile::PadButton;
+use evdev::{BusType, Key};
+use serde::Deserialize;
+
+/// Name of player 1's m2joy pad, kept for existing RetroArch setups.
+pub const PAD_NAME: &str = "m2joy Stick";
+const PAD_PRODUCT: u16 = 0x5678;
+
+/// USB vendor ID of every m2joy pad.
+pub const PAD_VENDOR: u16 = 0x1234;
+
+/// Which controller the virtual pad passes itself off as. Unset fields take
+/// the preset's value.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub preset: Preset,
+    pub name: Option<String>,
+    pub bus: Option<Bus>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub version: Option<u16>,
+}
+
+/// A controller's IDs and the layout its kernel driver reports, so SDL,
+/// Steam, Wine and emulators recognise the pad without manual mapping.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// m2joy's own pad, numbered per player.
+    #[default]
+    M2joy,
+    /// Wired Xbox 360 pad (xpad).
+    Xbox360,
+    /// DualShock 4 over USB (hid-playstation).
+    Ds4,
+    /// Switch Pro Controller over USB (hid-nintendo).
+    SwitchPro,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bus {
+    Usb,
+    Bluetooth,
+    Virtual,
+}
+
+/// Range of a stick axis as the driver reports it.
+#[derive(Debug, Clone, Copy)]
+pub struct StickRange {
+    pub min: i32,
+    pub max: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+}
+
+impl Preset {
+    /// Key code for a pad button, or None for D-pad directions (reported on
+    /// the hat axes) and buttons the controller lacks.
+    pub fn key(self, button: PadButton) -> Option<Key> {
+        match (self, button) {
+            // xpad reports the left face button as BTN_X, which is the code
+            // of BTN_NORTH, and the top one as BTN_Y
+            (Preset::Xbox360, PadButton::North) => Some(Key::BTN_WEST),
+            (Preset::Xbox360, PadButton::West) => Some(Key::BTN_NORTH),
+            // Triggers are axes only
+            (Preset::Xbox360, PadButton::L2 | PadButton::R2) => None,
+            _ => button.key(),
+        }
+    }
+
+    /// Every key the controller has, in key code order — the order
+    /// RetroArch's udev driver numbers them in (see the autoconfig).
+    pub fn keys(self) -> Vec<Key> {
+        let mut keys: Vec<Key> = PadButton::ALL
+            .into_iter()
+            .filter_map(|b| self.key(b))
+            .collect();
+        if self == Preset::SwitchPro {
+            // Capture, never pressed
+            keys.push(Key::BTN_Z);
+        }
+        keys.sort_by_key(|k| k.code());
+        keys
+    }
+
+    pub fn stick_range(self) -> StickRange {
+        let (min, max, fuzz, flat) = match self {
+            Preset::M2joy => (-32767, 32767, 0, 0),
+            Preset::Xbox360 => (-32768, 32767, 16, 128),
+            Preset::Ds4 => (0, 255, 0, 0),
+            Preset::SwitchPro => (-32767, 32767, 250, 500),
+        };
+        StickRange {
+            min,
+            max,
+            fuzz,
+            flat,
+        }
+    }
+
+    /// Whether L2 and R2 have analog axes (ABS_Z and ABS_RZ). Without,
+    /// they are buttons only.
+    pub fn analog_triggers(self) -> bool {
+        self != Preset::SwitchPro
+    }
+
+    /// Labels of the south, east, north and west face buttons, L1, R1, L2
+    /// and R2, as printed on the controller.
+    pub fn labels(self) -> [&'static str; 8] {
+        match self {
+            Preset::M2joy => ["A", "B", "X", "Y", "L1", "R1", "L2", "R2"],
+            Preset::Xbox360 => ["A", "B", "Y", "X", "LB", "RB", "LT", "RT"],
+            Preset::Ds4 => [
+                "Cross", "Circle", "Triangle", "Square", "L1", "R1", "L2", "R2",
+            ],
+            Preset::SwitchPro => ["B", "A", "X", "Y", "L", "R", "ZL", "ZR"],
+        }
+    }
+}
+
+/// The virtual pad's name, IDs and layout.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub bus: BusType,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    pub preset: Preset,
+}
+
+impl Identity {
+    /// The identity of a player's (1-based) pad. m2joy's own pads are
+    /// distinct per player so RetroArch can tell them apart; the others
+    /// look like the real controller, as several of them would.
+    pub fn new(config: &IdentityConfig, number: usize) -> Self {
+        let (name, bus, vendor, product, version) = match config.preset {
+            Preset::M2joy if number == 1 => (
+                PAD_NAME.to_string(),
+                BusType::BUS_VIRTUAL,
+                PAD_VENDOR,
+                PAD_PRODUCT,
+                1,
+            ),
+            Preset::M2joy => (
+                format!("{} {}", PAD_NAME, number),
+                BusType::BUS_VIRTUAL,
+                PAD_VENDOR,
+                PAD_PRODUCT + number as u16 - 1,
+                1,
+            ),
+            Preset::Xbox360 => (
+                "Microsoft X-Box 360 pad".to_string(),
+                BusType::BUS_USB,
+                0x045e,
+                0x028e,
+                0x0114,
+            ),
+            Preset::Ds4 => (
+                "Sony Interactive Entertainment Wireless Controller".to_string(),
+                BusType::BUS_USB,
+                0x054c,
+                0x09cc,
+                0x8111,
+            ),
+            Preset::SwitchPro => (
+                "Nintendo Switch Pro Controller".to_string(),
+                BusType::BUS_USB,
+                0x057e,
+                0x2009,
+                0x8111,
+            ),
+        };
+        Self {
+            name: config.name.clone().unwrap_or(name),
+            bus: match config.bus {
+                Some(Bus::Usb) => BusType::BUS_USB,
+                Some(Bus::Bluetooth) => BusType::BUS_BLUETOOTH,
+                Some(Bus::Virtual) => BusType::BUS_VIRTUAL,
+                None => bus,
+            },
+            vendor: config.vendor.unwrap_or(vendor),
+            product: config.product.unwrap_or(product),
+            version: config.version.unwrap_or(version),
+            preset: config.preset,
+        }
+    }
+}
+
+impl IdentityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.name {
+            // uinput names hold 79 bytes, and the motion sensors add a
+            // suffix
+            if name.is_empty() || name.len() > 64 {
+                return Err(format!(
+                    "identity.name must be 1 to 64 bytes, got {}",
+                    name.len()
+                ));
+            }
+            // It names the RetroArch autoconfig file and is quoted in it
+            if name.contains(['/', '\0', '"']) {
+                return Err(format!(
+                    "identity.name must not contain '/', '\"' or NUL, got '{}'",
+                    name.escape_default()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str) -> IdentityConfig {
+        IdentityConfig {
+            name: Some(name.to_string()),
+            ..IdentityConfig::default()
+        }
+    }
+
+    #[test]
+    fn name_must_be_a_safe_file_name() {
+        assert!(named("My Pad 2").validate().is_ok());
+        assert!(named("../../.bashrc").validate().is_err());
+        assert!(named("a/b").validate().is_err());
+        assert!(named("nul\0").validate().is_err());
+        assert!(named("say \"hi\"").validate().is_err());
+        assert!(named("").validate().is_err());
+        assert!(named(&"x".repeat(65)).validate().is_err());
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_preset() {
+        let config = IdentityConfig {
+            preset: Preset::Xbox360,
+            product: Some(0x1234),
+            ..IdentityConfig::default()
+        };
+        let identity = Identity::new(&config, 2);
+        assert_eq!(identity.name, "Microsoft X-Box 360 pad");
+        assert_eq!((identity.vendor, identity.product), (0x045e, 0x1234));
+    }
+
+    #[test]
+    fn m2joy_pads_are_numbered_per_player() {
+        let config = IdentityConfig::default();
+        assert_eq!(Identity::new(&config, 1).name, PAD_NAME);
+        let second = Identity::new(&config, 2);
+        assert_eq!(second.name, "m2joy Stick 2");
+        assert_eq!(second.product, PAD_PRODUCT + 1);
+    }
+
+    #[test]
+    fn xbox_360_has_no_digital_triggers() {
+        let keys = Preset::Xbox360.keys();
+        assert_eq!(keys.len(), 11);
+        assert!(!keys.contains(&Key::BTN_TL2));
+        assert!(Preset::SwitchPro.keys().contains(&Key::BTN_Z));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::device_match::DeviceSelector;
+use crate::mapping::Input;
+use crate::mouse::ButtonEvent;
+use crate::profile::Stick;
+use evdev::Key;
+use serde::Deserialize;
+
+/// A keyboard feeding the same pad as the mouse: movement keys drive a
+/// stick, every other key goes through the profile's `map`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// Event node path or match rule; auto-detected when absent.
+    pub device: Option<DeviceSelector>,
+    pub stick: Stick,
+    pub up: Input,
+    pub left: Input,
+    pub down: Input,
+    pub right: Input,
+    /// From rest to full deflection.
+    pub ramp_up_ms: f32,
+    /// From full deflection back to rest.
+    pub ramp_down_ms: f32,
+    /// While held, movement only goes this far.
+    pub walk: Option<Input>,
+    pub walk_scale: f32,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            stick: Stick::Left,
+            up: Input::Key(Key::KEY_W),
+            left: Input::Key(Key::KEY_A),
+            down: Input::Key(Key::KEY_S),
+            right: Input::Key(Key::KEY_D),
+            ramp_up_ms: 80.0,
+            ramp_down_ms: 50.0,
+            walk: None,
+            walk_scale: 0.5,
+        }
+    }
+}
+
+impl KeyboardConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let keys = [
+            ("up", Some(self.up)),
+            ("left", Some(self.left)),
+            ("down", Some(self.down)),
+            ("right", Some(self.right)),
+            ("walk", self.walk),
+        ];
+        for (i, (name, input)) in keys.iter().enumerate() {
+            let Some(input) = input else {
+                continue;
+            };
+            if !matches!(input, Input::Key(_)) {
+                return Err(format!(
+                    "keyboard.{}: '{}' must be a single key",
+                    name, input
+                ));
+            }
+            if keys[i + 1..]
+                .iter()
+                .any(|(_, other)| *other == Some(*input))
+            {
+                return Err(format!("keyboard: '{}' is used twice", input));
+            }
+        }
+        if self.ramp_up_ms < 0.0 || self.ramp_down_ms < 0.0 {
+            return Err("keyboard ramp_up_ms and ramp_down_ms must be >= 0".into());
+        }
+        if !(self.walk_scale > 0.0 && self.walk_scale <= 1.0) {
+            return Err(format!(
+                "keyboard walk_scale must be in (0, 1], got {}",
+                self.walk_scale
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Movement keys as a stick that ramps towards where they point instead of
+/// jumping there, like a thumb pushing the stick.
+pub struct Movement {
+    config: KeyboardConfig,
+    /// Up, left, down, right, walk.
+    down: [bool; 5],
+    pos: (f32, f32),
+}
+
+impl Movement {
+    /// Movement for a profile's keyboard; it never moves without one.
+    pub fn new(config: Option<&KeyboardConfig>) -> Self {
+        Self {
+            config: config.cloned().unwrap_or_default(),
+            down: [false; 5],
+            pos: (0.0, 0.0),
+        }
+    }
+
+    /// The stick this moves.
+    pub fn stick(&self) -> Stick {
+        self.config.stick
+    }
+
+    /// Take a key event. Returns whether it is one of the movement keys,
+    /// which then does nothing else.
+    pub fn feed(&mut self, event: &ButtonEvent) -> bool {
+        let c = &self.config;
+        let keys = [
+            Some(c.up),
+            Some(c.left),
+            Some(c.down),
+            Some(c.right),
+            c.walk,
+        ];
+        match keys.iter().position(|&k| k == Some(event.input)) {
+            Some(i) => {
+                self.down[i] = event.value != 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Advance by `dt` seconds and return the stick position.
+    pub fn update(&mut self, dt: f32) -> (f32, f32) {
+        let [up, left, down, right, walk] = self.down.map(|d| d as i32 as f32);
+        let (mut x, mut y) = (right - left, down - up);
+        // Diagonals reach the rim, not the corner
+        let len = x.hypot(y);
+        if len > 1.0 {
+            x /= len;
+            y /= len;
+        }
+        if walk > 0.0 {
+            x *= self.config.walk_scale;
+            y *= self.config.walk_scale;
+        }
+        self.pos = (self.ramp(self.pos.0, x, dt), self.ramp(self.pos.1, y, dt));
+        self.pos
+    }
+
+    /// Move one axis from `pos` towards `target`: back towards the centre
+    /// at the ramp-down rate, then out at the ramp-up rate, so reversing
+    /// pushes out on the other side as fast as starting from rest.
+    fn ramp(&self, mut pos: f32, target: f32, mut dt: f32) -> f32 {
+        if pos * target < 0.0 || target.abs() < pos.abs() {
+            let stop = if pos * target < 0.0 { 0.0 } else { target };
+            let needed = (pos - stop).abs() * self.config.ramp_down_ms / 1000.0;
+            if needed >= dt {
+                return step(pos, stop, dt, self.config.ramp_down_ms);
+            }
+            pos = stop;
+            dt -= needed;
+        }
+        step(pos, target, dt, self.config.ramp_up_ms)
+    }
+
+    /// Let go of every key, e.g. when the keyboard is released.
+    pub fn reset(&mut self) {
+        self.down = [false; 5];
+        self.pos = (0.0, 0.0);
+    }
+}
+
+/// Move from `pos` towards `target` for `dt` seconds at a rate of full
+/// deflection per `ms`.
+fn step(pos: f32, target: f32, dt: f32, ms: f32) -> f32 {
+    if ms <= 0.0 {
+        return target;
+    }
+    let step = dt * 1000.0 / ms;
+    if target > pos {
+        (pos + step).min(target)
+    } else {
+        (pos - step).max(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn movement(walk: bool) -> Movement {
+        Movement::new(Some(&KeyboardConfig {
+            walk: walk.then_some(Input::Key(Key::KEY_LEFTSHIFT)),
+            ..KeyboardConfig::default()
+        }))
+    }
+
+    fn key(m: &mut Movement, key: Key, down: bool) {
+        let event = ButtonEvent {
+            time: Duration::ZERO,
+            input: Input::Key(key),
+            value: down as i32,
+        };
+        assert!(m.feed(&event));
+    }
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    #[test]
+    fn ramps_up_and_down_at_their_rates() {
+        let mut m = movement(false);
+        key(&mut m, Key::KEY_D, true);
+        // 80ms to full deflection
+        assert!(close(m.update(0.040), (0.5, 0.0)));
+        assert!(close(m.update(0.040), (1.0, 0.0)));
+        assert!(close(m.update(0.040), (1.0, 0.0)));
+        // 50ms back to rest
+        key(&mut m, Key::KEY_D, false);
+        assert!(close(m.update(0.025), (0.5, 0.0)));
+        assert!(close(m.update(0.050), (0.0, 0.0)));
+    }
+
+    #[test]
+    fn reversing_ramps_up_once_past_the_centre() {
+        let mut m = movement(false);
+        key(&mut m, Key::KEY_D, true);
+        m.update(0.080);
+        key(&mut m, Key::KEY_D, false);
+        key(&mut m, Key::KEY_A, true);
+        // 50ms back to the centre, then 20ms of the 80ms ramp up
+        assert!(close(m.update(0.070), (-0.25, 0.0)));
+        assert!(close(m.update(0.060), (-1.0, 0.0)));
+    }
+
+    #[test]
+    fn diagonals_reach_the_rim() {
+        let mut m = movement(false);
+        key(&mut m, Key::KEY_W, true);
+        key(&mut m, Key::KEY_D, true);
+        let (x, y) = m.update(1.0);
+        assert!(close((x, y), (0.5f32.sqrt(), -(0.5f32.sqrt()))));
+        // Opposite keys cancel out
+        key(&mut m, Key::KEY_S, true);
+        assert!(close(m.update(1.0), (1.0, 0.0)));
+    }
+
+    #[test]
+    fn walk_key_scales_movement() {
+        let mut m = movement(true);
+        key(&mut m, Key::KEY_LEFTSHIFT, true);
+        key(&mut m, Key::KEY_A, true);
+        key(&mut m, Key::KEY_S, true);
+        let half = 0.5 * 0.5f32.sqrt();
+        assert!(close(m.update(1.0), (-half, half)));
+        // Letting go of walk ramps out to the rim
+        key(&mut m, Key::KEY_LEFTSHIFT, false);
+        assert!(close(m.update(1.0), (-(0.5f32.sqrt()), 0.5f32.sqrt())));
+        // Other keys are not movement
+        let other = ButtonEvent {
+            time: Duration::ZERO,
+            input: Input::Key(Key::KEY_E),
+            value: 1,
+        };
+        assert!(!m.feed(&other));
+    }
+}
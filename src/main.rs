@@ -1,261 +1,409 @@
+mod calibrate;
 mod config;
+mod curve;
+mod deadzone;
+mod device_match;
+mod devices;
+mod ff;
+mod filter;
+mod gamepad;
+mod gate;
+mod gesture;
+mod hotplug;
+mod identity;
+mod keyboard;
+mod mapping;
+mod motion;
 mod mouse;
+mod player;
+mod probe;
+mod profile;
+mod ring;
+mod source;
+mod status;
+mod velocity;
 mod virtual_pad;
 
 use clap::Parser;
-use config::Config;
-use mouse::{find_mouse_device, MouseReader, MouseState};
-use std::sync::atomic::{AtomicBool, Ordering};
+use config::{Command, Config};
+use device_match::{DeviceFilter, DeviceKind, DeviceSelector};
+use evdev::AbsoluteAxisType;
+use ff::{FfReporter, FfSink};
+use identity::{Identity, Preset, PAD_VENDOR};
+use mapping::{Action, Output};
+use motion::MotionSensor;
+use mouse::find_device;
+use player::Player;
+use profile::{MouseSpec, PadButton, PlayerSettings, Profile};
+use source::{GamepadSource, KeyboardSource, MouseSource};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use virtual_pad::VirtualPad;
 
-static QUIT: AtomicBool = AtomicBool::new(false);
-pub(crate) static TOGGLE: AtomicBool = AtomicBool::new(false);
-
-/// Scale factor for stick deflection.
-/// With EMA_DECAY=0.98, steady-state gain ≈ 1/(1-0.98) = 50.
-/// A typical slow mouse delta of ~2/report → EMA ≈ 5-8 → output ≈ 2500-4000.
-/// A fast flick of ~20/report → EMA ≈ 40-60 → output ≈ 20000-32767.
-const BASE_SCALE: f32 = 500.0;
-
-/// EMA decay per tick (1ms). 0.98 ≈ 34ms half-life.
-/// Between 125Hz mouse reports (~8ms), decays to 0.98^8 ≈ 0.85 — moderate hold.
-/// High enough to smooth between reports, low enough to not accumulate wildly.
-const EMA_DECAY: f32 = 0.98;
+pub(crate) static QUIT: AtomicBool = AtomicBool::new(false);
+/// Players with a pending toggle, one bit each (bit 0 = player 1).
+pub(crate) static TOGGLE: AtomicU32 = AtomicU32::new(0);
 
 fn main() {
-    // Handle "m2joy toggle" / "m2joy quit" before clap parsing.
-    // These send a signal to the running instance and exit immediately.
-    if let Some(cmd) = std::env::args().nth(1) {
-        match cmd.as_str() {
-            "toggle" => {
-                send_to_running(libc::SIGUSR1, "Toggle");
-                return;
-            }
-            "quit" => {
-                send_to_running(libc::SIGTERM, "Quit");
-                return;
-            }
-            _ => {}
+    let config = Config::parse();
+
+    // "m2joy toggle" / "m2joy quit" send a signal to the running instance
+    // and exit immediately; their errors are printed plainly.
+    let result = match config.command {
+        Some(Command::Toggle { player }) => {
+            send_to_running(libc::SIGUSR1, "Toggle", player).map_err(|e| eprintln!("{}", e))
+        }
+        Some(Command::Quit) => {
+            send_to_running(libc::SIGTERM, "Quit", None).map_err(|e| eprintln!("{}", e))
+        }
+        Some(Command::Status) => {
+            status::print();
+            Ok(())
+        }
+        _ => {
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+                .format_timestamp_millis()
+                .init();
+            run(&config).map_err(|e| e.lines().for_each(|line| log::error!("{}", line)))
         }
+    };
+    if result.is_err() {
+        std::process::exit(1);
     }
+}
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_millis()
-        .init();
+/// Everything but the signalling commands. Errors may span several lines,
+/// e.g. a hint on how to fix them.
+fn run(config: &Config) -> Result<(), String> {
+    let settings = profile::resolve(config).map_err(|e| format!("Failed to load config: {}", e))?;
+    let profile_name = &settings.primary().profile_name;
+    let profile = &settings.primary().profile;
+
+    if let Some(Command::CalibrateDeadzone) = config.command {
+        signal_setup();
+        let name = profile_name.as_deref().unwrap_or(profile::DEFAULT_PROFILE);
+        calibrate::deadzone(profile::config_path(config).as_deref(), name, profile);
+        return Ok(());
+    }
 
-    let config = Config::parse();
+    if let Some(Command::ListDevices) = config.command {
+        devices::list(profile);
+        return Ok(());
+    }
+
+    if let Some(Command::PickDevice) = config.command {
+        signal_setup();
+        let name = profile_name.as_deref().unwrap_or(profile::DEFAULT_PROFILE);
+        devices::pick(profile::config_path(config).as_deref(), name);
+        return Ok(());
+    }
+
+    if let Some(Command::Probe) = config.command {
+        signal_setup();
+        let path = resolve_device_path(profile, &profile.mice()[0], &[], &mut find_device)?;
+        probe::run(&path);
+        return Ok(());
+    }
 
+    let multiplayer = settings.players.len() > 1;
     println!("m2joy - Mouse-to-Joystick for RetroArch");
-    println!("  Sensitivity: {:.2}", config.sensitivity);
-    println!("  Invert Y:    {}", config.invert_y);
-    println!("  Output:      {} stick", if config.left_stick { "left" } else { "right" });
-    println!();
+    for (i, p) in settings.players.iter().enumerate() {
+        if multiplayer {
+            println!("  Player {}", i + 1);
+        }
+        let profile = &p.profile;
+        println!(
+            "  Profile:     {}",
+            p.profile_name.as_deref().unwrap_or("(defaults)")
+        );
+        println!("  Sensitivity: {:.2}", profile.sensitivity);
+        println!("  Invert Y:    {}", profile.invert_y);
+        if profile.mice.len() > 1 {
+            println!("  Mice:        {}", profile.mice.len());
+        } else {
+            println!("  Output:      {} stick", profile.stick.name());
+        }
+        println!();
+    }
 
     signal_setup();
 
-    // Find mouse device
-    let device_path = match &config.device {
-        Some(path) => path.clone(),
-        None => match find_mouse_device() {
-            Some(p) => {
-                let s = p.to_string_lossy().to_string();
-                log::info!("Auto-detected mouse: {}", s);
-                s
+    // Find every mouse, keyboard and gamepad before creating any pad
+    let assigned = assign_devices(&settings.players, find_device)?;
+
+    let mut players = Vec::with_capacity(settings.players.len());
+    let mut pad_names = Vec::with_capacity(settings.players.len());
+    for (i, (p, devices)) in settings.players.iter().zip(assigned).enumerate() {
+        let PlayerDevices {
+            mice: paths,
+            keyboard,
+            gamepad,
+        } = devices;
+        let mice = p.profile.mice();
+        let number = i + 1;
+        let label = if multiplayer {
+            format!("P{}: ", number)
+        } else {
+            String::new()
+        };
+
+        let active = Arc::new(AtomicBool::new(false));
+
+        // The physical gamepad first: the virtual one may offer its force
+        // feedback effects
+        let gamepad = match gamepad {
+            Some(path) => Some(
+                GamepadSource::open(&path, Arc::clone(&active), p.profile.identity.preset)
+                    .map_err(|e| {
+                        format!(
+                            "Failed to open gamepad device: {}\nCheck permissions on {}",
+                            e, path
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+
+        // Create virtual gamepad
+        let identity = Identity::new(&p.profile.identity, number);
+        let pad_name = identity.name.clone();
+        pad_names.push(format!("'{}'", pad_name));
+        let ff_types = p
+            .profile
+            .ff
+            .offered(gamepad.as_ref().and_then(|g| g.ff_types.as_deref()));
+        let pad = VirtualPad::new(
+            &identity,
+            p.profile.deadzone,
+            p.profile.gate,
+            ff_types.as_deref(),
+        )
+        .map_err(|e| {
+            format!(
+                "Failed to create virtual gamepad: {}\n\
+                 Do you have /dev/uinput access? Try: sudo modprobe uinput",
+                e
+            )
+        })?;
+        install_retroarch_autoconfig(&identity);
+
+        let mut sources = Vec::with_capacity(mice.len());
+        for (spec, path) in mice.iter().zip(&paths) {
+            let source = MouseSource::open(path, spec, &p.profile, &settings, Arc::clone(&active))
+                .map_err(|e| {
+                    format!(
+                        "Failed to open mouse device: {}\nCheck permissions on {}",
+                        e, path
+                    )
+                })?;
+            sources.push(source);
+        }
+        if multiplayer || sources.len() > 1 {
+            for s in &sources {
+                log::info!(
+                    "{}'{}' -> {} stick of '{}'",
+                    label,
+                    s.name,
+                    s.stick.name(),
+                    pad_name
+                );
             }
-            None => {
-                log::error!("No mouse device found. Are you in the 'input' group?");
-                log::error!("Try: sudo usermod -aG input $USER (then re-login)");
-                std::process::exit(1);
+        }
+        let mut player = Player::new(number, p, pad, sources, Arc::clone(&active), label.clone())
+            .with_profiles(settings.profiles.clone());
+        if let Some(path) = keyboard {
+            let keyboard = KeyboardSource::open(&path, Arc::clone(&active)).map_err(|e| {
+                format!(
+                    "Failed to open keyboard device: {}\nCheck permissions on {}",
+                    e, path
+                )
+            })?;
+            if multiplayer {
+                log::info!("{}'{}' -> '{}'", label, keyboard.name, pad_name);
+            }
+            player = player.with_keyboard(keyboard);
+            // A grabbed keyboard no longer reaches the compositor's keybinds
+            let release = p
+                .profile
+                .bindings()
+                .values()
+                .chain(p.profile.layers.values().flat_map(|l| l.map.values()))
+                .any(|b| b.output == Output::Action(Action::ReleaseGrab));
+            if !release {
+                log::warn!(
+                    "{}The keyboard is grabbed with the mouse, so `m2joy toggle` keybinds stop working; \
+                     bind a key to release-grab, e.g. map.f12 = \"release-grab\"",
+                    label
+                );
             }
-        },
-    };
-
-    // Create virtual gamepad
-    let mut pad = match VirtualPad::new(config.left_stick) {
-        Ok(p) => p,
-        Err(e) => {
-            log::error!("Failed to create virtual gamepad: {}", e);
-            log::error!("Do you have /dev/uinput access? Try: sudo modprobe uinput");
-            std::process::exit(1);
         }
-    };
-
-    install_retroarch_autoconfig();
-
-    // Spawn mouse reader thread
-    let mouse_state = Arc::new(MouseState::new());
-    let mouse_state_clone = Arc::clone(&mouse_state);
-    let device_path_clone = device_path.clone();
-
-    let mouse_thread = std::thread::Builder::new()
-        .name("mouse-reader".into())
-        .spawn(move || {
-            match MouseReader::new(&device_path_clone, mouse_state_clone) {
-                Ok(mut reader) => reader.run(),
-                Err(e) => {
-                    log::error!("Failed to open mouse device: {}", e);
-                    log::error!("Check permissions on {}", device_path_clone);
+        if let Some(g) = gamepad {
+            log::info!(
+                "{}'{}' merged into '{}'{}",
+                label,
+                g.name,
+                pad_name,
+                if g.ff_types.is_some() && p.profile.ff.sink == FfSink::Gamepad {
+                    ", with force feedback"
+                } else {
+                    ""
                 }
-            }
-        })
-        .expect("Failed to spawn mouse thread");
+            );
+            player = player.with_gamepad(g);
+        }
+        if let Some(config) = &p.profile.motion {
+            let motion = MotionSensor::new(&identity, config)
+                .map_err(|e| format!("Failed to create motion sensors: {}", e))?;
+            player = player.with_motion(motion);
+        }
+        match FfReporter::spawn(&p.profile.ff, number, label.clone()) {
+            Ok(Some(reporter)) => player = player.with_ff_reporter(reporter),
+            Ok(None) => {}
+            Err(e) => log::warn!("{}Failed to start force feedback reporting: {}", label, e),
+        }
+        players.push(player);
+    }
 
-    println!("Toggle: m2joy toggle");
+    if multiplayer {
+        println!("Toggle: m2joy toggle [--player N]");
+    } else {
+        println!("Toggle: m2joy toggle");
+    }
     println!("Quit:   m2joy quit");
-    println!("Configure RetroArch to use 'm2joy Stick' as a controller.");
+    if multiplayer {
+        println!(
+            "Configure RetroArch to use {} as controllers.",
+            pad_names.join(", ")
+        );
+    } else {
+        println!(
+            "Configure RetroArch to use {} as a controller.",
+            pad_names[0]
+        );
+    }
     println!();
 
-    // Main 1kHz loop — EMA smoothed velocity
-    // New deltas are added at full strength (instant response). Between mouse reports,
-    // the EMA decays smoothly so the stick value persists long enough for RetroArch's
-    // per-frame polling (~16ms) to always see meaningful deflection.
-    let tick = Duration::from_micros(1000);
-    let scale = BASE_SCALE * config.sensitivity;
-    let y_sign = if config.invert_y { -1.0f32 } else { 1.0 };
-
-    let mut ema_x: f32 = 0.0;
-    let mut ema_y: f32 = 0.0;
-    let mut idle_ticks: u32 = 0;
-    let mut prev_sx: i32 = 0;
-    let mut prev_sy: i32 = 0;
-
-    // Debug
+    // Main loop (1kHz by default) — filtered velocity
+    // The filter turns sparse per-tick deltas into a velocity that persists between
+    // mouse reports, long enough for RetroArch's per-frame polling (~16ms) to always
+    // see meaningful deflection. Every constant is in real time and each iteration
+    // feeds the filter its measured dt, so an overrun tick does not change behaviour.
+    // With several players the fastest tick rate wins.
+    let tick_rate = players
+        .iter()
+        .map(|p| p.profile.tick_rate)
+        .max()
+        .unwrap_or(1000);
+    let tick = Duration::from_secs_f64(1.0 / tick_rate as f64);
     let debug = config.debug;
-    let mut dbg_tick: u32 = 0;
-    let mut dbg_raw_dx: i64 = 0;
-    let mut dbg_raw_dy: i64 = 0;
-    let mut dbg_samples: u32 = 0;
+
+    let mut last = Instant::now();
+    let mut deadline = last + tick;
 
     loop {
-        let tick_start = std::time::Instant::now();
+        let now = Instant::now();
+        let dt = now.duration_since(last).as_secs_f32().max(1e-6);
+        last = now;
 
-        if QUIT.load(Ordering::Relaxed) || mouse_state.quit.load(Ordering::Relaxed) {
+        if QUIT.load(Ordering::Relaxed) || players.iter().any(Player::quit_requested) {
             break;
         }
+        if players.iter().any(Player::reader_died) {
+            players.iter_mut().for_each(Player::neutral);
+            return Err("Mouse reader stopped unexpectedly".into());
+        }
 
-        if mouse_state.active.load(Ordering::Relaxed) {
-            let (dx, dy) = mouse_state.drain();
-
-            if debug {
-                dbg_raw_dx += dx as i64;
-                dbg_raw_dy += dy as i64;
-                if dx != 0 || dy != 0 {
-                    dbg_samples += 1;
-                }
-            }
-
-            // EMA with high decay (0.99): between 125Hz mouse reports the value
-            // only decays to 92% — much less sawtooth than 0.96 (which hit 72%).
-            // Steady movement produces a stable plateau.
-            ema_x = ema_x * EMA_DECAY + dx as f32;
-            ema_y = ema_y * EMA_DECAY + dy as f32 * y_sign;
-
-            // Track idle time to force quick stop when mouse stops
-            if dx == 0 && dy == 0 {
-                idle_ticks += 1;
-            } else {
-                idle_ticks = 0;
-            }
-
-            // After 30ms of no mouse data, force zero (kills the long decay tail)
-            if idle_ticks > 30 {
-                ema_x = 0.0;
-                ema_y = 0.0;
-            }
-
-            let sx = (ema_x * scale) as i32;
-            let sy = (ema_y * scale) as i32;
-
-            // Only emit when values actually change
-            if sx != prev_sx || sy != prev_sy {
-                if let Err(e) = pad.emit_stick(sx, sy) {
-                    log::warn!("Failed to emit stick: {}", e);
-                }
-                prev_sx = sx;
-                prev_sy = sy;
-            }
-
-            // Forward mouse buttons as triggers: left click → R2, right click → L2
-            if mouse_state
-                .btns_dirty
-                .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
-                .is_ok()
-            {
-                let l2 = mouse_state.btn_right.load(Ordering::Relaxed);
-                let r2 = mouse_state.btn_left.load(Ordering::Relaxed);
-                if let Err(e) = pad.emit_triggers(l2, r2) {
-                    log::warn!("Failed to emit triggers: {}", e);
-                }
+        // External toggle (SIGUSR1 via `m2joy toggle`); the readers follow
+        // their player's flag and grab or release their devices.
+        let toggles = TOGGLE.swap(0, Ordering::Relaxed);
+        for p in &mut players {
+            if toggles & (1 << (p.number - 1)) != 0 {
+                p.toggle();
             }
+        }
 
-            // Debug: print every 100 ticks (100ms)
-            if debug {
-                dbg_tick += 1;
-                if dbg_tick >= 100 {
-                    if dbg_raw_dx != 0 || dbg_raw_dy != 0 || ema_x != 0.0 || ema_y != 0.0 {
-                        eprintln!(
-                            "[dbg] raw({:+5},{:+5}) n={:<3} ema({:+7.1},{:+7.1}) out({:+6},{:+6})",
-                            dbg_raw_dx,
-                            dbg_raw_dy,
-                            dbg_samples,
-                            ema_x,
-                            ema_y,
-                            sx.clamp(-32767, 32767),
-                            sy.clamp(-32767, 32767),
-                        );
-                    }
-                    dbg_tick = 0;
-                    dbg_raw_dx = 0;
-                    dbg_raw_dy = 0;
-                    dbg_samples = 0;
-                }
-            }
-        } else {
-            // Not active — center stick
-            if ema_x != 0.0 || ema_y != 0.0 || prev_sx != 0 || prev_sy != 0 {
-                ema_x = 0.0;
-                ema_y = 0.0;
-                idle_ticks = 0;
-                prev_sx = 0;
-                prev_sy = 0;
-                let _ = pad.emit_stick(0, 0);
-            }
+        for p in &mut players {
+            p.tick(dt, now, debug);
+        }
+        // Every player's flag is taken, not just the first changed one's
+        if players
+            .iter_mut()
+            .fold(false, |changed, p| p.take_status_change() | changed)
+        {
+            status::write(&players.iter().map(Player::status).collect::<Vec<_>>());
         }
 
-        let elapsed = tick_start.elapsed();
-        if elapsed < tick {
-            spin_sleep::sleep(tick - elapsed);
+        // Absolute deadlines: sleep overshoot does not accumulate. If the loop
+        // was starved for more than a tick, resync instead of bursting to catch
+        // up — the measured dt already accounts for the gap.
+        let now = Instant::now();
+        if deadline > now {
+            spin_sleep::sleep(deadline - now);
+        } else if now - deadline > tick {
+            deadline = now;
         }
+        deadline += tick;
     }
 
-    // Center stick before exit
-    let _ = pad.emit_stick(0, 0);
+    // Center sticks before exit
+    players.iter_mut().for_each(Player::neutral);
 
     log::info!("Shutting down...");
-    mouse_state.quit.store(true, Ordering::Relaxed);
-    let _ = mouse_thread.join();
+    status::remove();
+    for p in players {
+        p.stop();
+    }
     log::info!("Done");
+    Ok(())
 }
 
 fn signal_setup() {
+    let handler = signal_handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let toggle = toggle_handler
+        as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+        as libc::sighandler_t;
     unsafe {
-        libc::signal(libc::SIGINT, signal_handler as libc::sighandler_t);
-        libc::signal(libc::SIGTERM, signal_handler as libc::sighandler_t);
-        libc::signal(libc::SIGUSR1, signal_handler as libc::sighandler_t);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+
+        // SA_SIGINFO to read the player number sent with sigqueue()
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = toggle;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
     }
 }
 
-extern "C" fn signal_handler(sig: libc::c_int) {
-    match sig {
-        libc::SIGUSR1 => TOGGLE.store(true, Ordering::Relaxed),
-        _ => QUIT.store(true, Ordering::Relaxed),
-    }
+extern "C" fn signal_handler(_sig: libc::c_int) {
+    QUIT.store(true, Ordering::Relaxed);
+}
+
+/// SIGUSR1: toggle the player in the signal's value (`m2joy toggle --player
+/// N`), or every player for a plain kill (`m2joy toggle`, `pkill -USR1`).
+extern "C" fn toggle_handler(
+    _sig: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _ctx: *mut libc::c_void,
+) {
+    let player = unsafe {
+        if (*info).si_code == libc::SI_QUEUE {
+            (*info).si_value().sival_ptr as usize
+        } else {
+            0
+        }
+    };
+    let mask = match player {
+        1..=player::MAX_PLAYERS => 1 << (player - 1),
+        _ => u32::MAX,
+    };
+    TOGGLE.fetch_or(mask, Ordering::Relaxed);
 }
 
 /// Find PID of a running m2joy instance by scanning /proc.
-fn find_running_instance() -> Option<i32> {
+pub(crate) fn find_running_instance() -> Option<i32> {
     let my_pid = std::process::id() as i32;
     for entry in std::fs::read_dir("/proc").ok()? {
         let entry = match entry {
@@ -280,77 +428,511 @@ fn find_running_instance() -> Option<i32> {
 
 /// Install RetroArch autoconfig so the virtual gamepad is recognized automatically.
 /// Only writes if the RetroArch autoconfig/udev directory exists.
-fn install_retroarch_autoconfig() {
+pub(crate) fn install_retroarch_autoconfig(identity: &Identity) {
     let home = match std::env::var("HOME") {
         Ok(h) => h,
         Err(_) => return,
     };
     let dir = std::path::PathBuf::from(&home).join(".config/retroarch/autoconfig/udev");
-    if !dir.is_dir() {
-        return;
+    if dir.is_dir() {
+        write_retroarch_autoconfig(&dir, identity);
     }
-    let path = dir.join("m2joy Stick.cfg");
-    if path.exists() {
+}
+
+/// Write a pad's autoconfig into RetroArch's udev autoconfig directory.
+fn write_retroarch_autoconfig(dir: &std::path::Path, identity: &Identity) {
+    let path = dir.join(format!("{}.cfg", identity.name));
+    let cfg = retroarch_autoconfig(identity);
+    // Rewrite files m2joy wrote, so indices match the current layout, but
+    // never one the user edited or another pad's from RetroArch's pack.
+    let existing = std::fs::read_to_string(&path).ok();
+    if existing.as_deref() == Some(cfg.as_str()) {
         return;
     }
-    // Axis indices are contiguous in RetroArch's udev driver:
-    // ABS_X→0, ABS_Y→1, ABS_RX→2, ABS_RY→3 (no ABS_Z gap)
-    let cfg = "\
-input_driver = \"udev\"
-input_device = \"m2joy Stick\"
-input_device_display_name = \"m2joy Stick\"
-input_vendor_id = \"4660\"
-input_product_id = \"22136\"
-input_b_btn = \"0\"
-input_a_btn = \"1\"
-input_x_btn = \"2\"
-input_y_btn = \"3\"
-input_l2_btn = \"4\"
-input_r2_btn = \"5\"
-input_l_x_plus_axis = \"+0\"
-input_l_x_minus_axis = \"-0\"
-input_l_y_plus_axis = \"+1\"
-input_l_y_minus_axis = \"-1\"
-input_r_x_plus_axis = \"+2\"
-input_r_x_minus_axis = \"-2\"
-input_r_y_plus_axis = \"+3\"
-input_r_y_minus_axis = \"-3\"
-input_b_btn_label = \"A\"
-input_a_btn_label = \"B\"
-input_x_btn_label = \"X\"
-input_y_btn_label = \"Y\"
-input_l2_btn_label = \"L2 (Right Click)\"
-input_r2_btn_label = \"R2 (Left Click)\"
-input_l_x_plus_axis_label = \"Left Analog Right\"
-input_l_x_minus_axis_label = \"Left Analog Left\"
-input_l_y_plus_axis_label = \"Left Analog Down\"
-input_l_y_minus_axis_label = \"Left Analog Up\"
-input_r_x_plus_axis_label = \"Right Analog Right\"
-input_r_x_minus_axis_label = \"Right Analog Left\"
-input_r_y_plus_axis_label = \"Right Analog Down\"
-input_r_y_minus_axis_label = \"Right Analog Up\"
-";
+    if let Some(existing) = &existing {
+        let ours = existing.lines().any(|l| {
+            l == AUTOCONFIG_MARKER || l == format!("input_vendor_id = \"{}\"", PAD_VENDOR)
+        });
+        if !ours {
+            log::info!(
+                "Kept RetroArch autoconfig {}: not written by m2joy",
+                path.display()
+            );
+            return;
+        }
+    }
     match std::fs::write(&path, cfg) {
+        Ok(_) if existing.is_some() => {
+            log::info!("Updated RetroArch autoconfig: {}", path.display())
+        }
         Ok(_) => log::info!("Installed RetroArch autoconfig: {}", path.display()),
         Err(e) => log::warn!("Could not write RetroArch autoconfig: {}", e),
     }
 }
 
-/// Send a signal to the running m2joy instance, or exit with an error.
-fn send_to_running(sig: libc::c_int, action: &str) {
+/// First line of every autoconfig m2joy writes; files without it are left
+/// alone. Files from versions before it are recognised by m2joy's vendor ID.
+const AUTOCONFIG_MARKER: &str =
+    "# Written by m2joy, which updates it; remove this line to keep your edits";
+
+/// RetroArch autoconfig for a pad's layout. Buttons are numbered in key
+/// code order (see `Preset::keys`), skipping codes the pad does not have.
+/// Axis indices are contiguous too, with the hat left out: ABS_X→0,
+/// ABS_Y→1, ABS_Z→2, ABS_RX→3, ABS_RY→4, ABS_RZ→5 with analog triggers.
+fn retroarch_autoconfig(identity: &Identity) -> String {
+    let preset = identity.preset;
+    let keys = preset.keys();
+    let [south, east, north, west, l1, r1, l2, r2] = preset.labels();
+    // m2joy's own pad notes the default trigger bindings
+    let (l2_btn, r2_btn) = if preset == Preset::M2joy {
+        (
+            format!("{} (Right Click)", l2),
+            format!("{} (Left Click)", r2),
+        )
+    } else {
+        (l2.to_string(), r2.to_string())
+    };
+    let buttons = [
+        ("b", PadButton::South, south.to_string()),
+        ("a", PadButton::East, east.to_string()),
+        ("x", PadButton::North, north.to_string()),
+        ("y", PadButton::West, west.to_string()),
+        ("l", PadButton::L1, l1.to_string()),
+        ("r", PadButton::R1, r1.to_string()),
+        ("l2", PadButton::L2, l2_btn),
+        ("r2", PadButton::R2, r2_btn),
+        ("select", PadButton::Select, "Select".to_string()),
+        ("start", PadButton::Start, "Start".to_string()),
+        ("menu_toggle", PadButton::Mode, "Mode".to_string()),
+        ("l3", PadButton::L3, "L3".to_string()),
+        ("r3", PadButton::R3, "R3".to_string()),
+    ];
+    let hat = [
+        ("up", "h0up", "D-Pad Up"),
+        ("down", "h0down", "D-Pad Down"),
+        ("left", "h0left", "D-Pad Left"),
+        ("right", "h0right", "D-Pad Right"),
+    ];
+    let mut axes = vec![AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y];
+    if preset.analog_triggers() {
+        axes.push(AbsoluteAxisType::ABS_Z);
+    }
+    axes.extend([AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY]);
+    if preset.analog_triggers() {
+        axes.push(AbsoluteAxisType::ABS_RZ);
+    }
+    let axis = |a| axes.iter().position(|&b| b == a).unwrap_or(0);
+    let triggers: &[_] = if preset.analog_triggers() {
+        &[
+            ("l2", AbsoluteAxisType::ABS_Z, l2),
+            ("r2", AbsoluteAxisType::ABS_RZ, r2),
+        ]
+    } else {
+        &[]
+    };
+    let sticks = [
+        (
+            "l_x",
+            AbsoluteAxisType::ABS_X,
+            "Left Analog",
+            ("Right", "Left"),
+        ),
+        (
+            "l_y",
+            AbsoluteAxisType::ABS_Y,
+            "Left Analog",
+            ("Down", "Up"),
+        ),
+        (
+            "r_x",
+            AbsoluteAxisType::ABS_RX,
+            "Right Analog",
+            ("Right", "Left"),
+        ),
+        (
+            "r_y",
+            AbsoluteAxisType::ABS_RY,
+            "Right Analog",
+            ("Down", "Up"),
+        ),
+    ];
+
+    let mut lines = vec![
+        AUTOCONFIG_MARKER.to_string(),
+        "input_driver = \"udev\"".to_string(),
+        format!("input_device = \"{}\"", identity.name),
+        format!("input_device_display_name = \"{}\"", identity.name),
+        format!("input_vendor_id = \"{}\"", identity.vendor),
+        format!("input_product_id = \"{}\"", identity.product),
+    ];
+    let present: Vec<_> = buttons
+        .iter()
+        .filter_map(|(name, button, label)| {
+            let key = preset.key(*button)?;
+            let index = keys.iter().position(|&k| k == key)?;
+            Some((name, index, label))
+        })
+        .collect();
+    for (name, index, _) in &present {
+        lines.push(format!("input_{}_btn = \"{}\"", name, index));
+    }
+    for (name, code, _) in hat {
+        lines.push(format!("input_{}_btn = \"{}\"", name, code));
+    }
+    for (name, a, _) in triggers {
+        lines.push(format!("input_{}_axis = \"+{}\"", name, axis(*a)));
+    }
+    for (name, a, _, _) in sticks {
+        let i = axis(a);
+        lines.push(format!("input_{}_plus_axis = \"+{}\"", name, i));
+        lines.push(format!("input_{}_minus_axis = \"-{}\"", name, i));
+    }
+    for (name, _, label) in &present {
+        lines.push(format!("input_{}_btn_label = \"{}\"", name, label));
+    }
+    for (name, _, label) in hat {
+        lines.push(format!("input_{}_btn_label = \"{}\"", name, label));
+    }
+    for (name, _, label) in triggers {
+        lines.push(format!(
+            "input_{}_axis_label = \"{} (Analog)\"",
+            name, label
+        ));
+    }
+    for (name, _, stick, (plus, minus)) in sticks {
+        lines.push(format!(
+            "input_{}_plus_axis_label = \"{} {}\"",
+            name, stick, plus
+        ));
+        lines.push(format!(
+            "input_{}_minus_axis_label = \"{} {}\"",
+            name, stick, minus
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Device paths for one player: its mice in `Profile::mice` order, and its
+/// keyboard and gamepad.
+struct PlayerDevices {
+    mice: Vec<String>,
+    keyboard: Option<String>,
+    gamepad: Option<String>,
+}
+
+/// Find every player's mice, keyboard and gamepad with `find` (normally
+/// `find_device`). Each entry skips the devices already taken by the ones
+/// before it, across all players.
+fn assign_devices(
+    players: &[PlayerSettings],
+    mut find: impl FnMut(&DeviceFilter, &[PathBuf]) -> Option<PathBuf>,
+) -> Result<Vec<PlayerDevices>, String> {
+    let mut taken: Vec<String> = Vec::new();
+    let mut assigned = Vec::with_capacity(players.len());
+    for p in players {
+        let mut mice = Vec::new();
+        for spec in p.profile.mice() {
+            let path = resolve_device_path(&p.profile, &spec, &taken, &mut find)?;
+            taken.push(path.clone());
+            mice.push(path);
+        }
+        let keyboard = resolve_extra_path(&p.profile, DeviceKind::Keyboard, &taken, &mut find)?;
+        taken.extend(keyboard.clone());
+        let gamepad = resolve_extra_path(&p.profile, DeviceKind::Gamepad, &taken, &mut find)?;
+        taken.extend(gamepad.clone());
+        assigned.push(PlayerDevices {
+            mice,
+            keyboard,
+            gamepad,
+        });
+    }
+    Ok(assigned)
+}
+
+/// Find the profile's keyboard or gamepad from its rule, or auto-detect it,
+/// skipping devices already in use. None when the profile has none.
+fn resolve_extra_path(
+    profile: &Profile,
+    kind: DeviceKind,
+    taken: &[String],
+    find: &mut impl FnMut(&DeviceFilter, &[PathBuf]) -> Option<PathBuf>,
+) -> Result<Option<String>, String> {
+    let (device, filter) = match kind {
+        DeviceKind::Keyboard => match (&profile.keyboard, profile.keyboard_filter()) {
+            (Some(k), Some(filter)) => (&k.device, filter),
+            _ => return Ok(None),
+        },
+        DeviceKind::Gamepad => match (&profile.gamepad, profile.gamepad_filter()) {
+            (Some(g), Some(filter)) => (&g.device, filter),
+            _ => return Ok(None),
+        },
+        DeviceKind::Mouse => return Ok(None),
+    };
+    if let Some(DeviceSelector::Path(path)) = device {
+        return Ok(Some(path.to_string_lossy().to_string()));
+    }
+    let taken: Vec<PathBuf> = taken.iter().map(PathBuf::from).collect();
+    match find(&filter, &taken) {
+        Some(p) => {
+            let s = p.to_string_lossy().to_string();
+            log::info!("Auto-detected {}: {}", kind.name(), s);
+            Ok(Some(s))
+        }
+        None => {
+            let problem = match device {
+                Some(DeviceSelector::Match(m)) => format!("No {} matches '{}'", kind.name(), m),
+                _ => format!("No {} device found", kind.name()),
+            };
+            Err(format!(
+                "{}\nRun `m2joy list-devices` to see why each device was skipped",
+                problem
+            ))
+        }
+    }
+}
+
+/// Find the device path for one mouse from its rule, or auto-detect it,
+/// skipping devices already used by other mice.
+fn resolve_device_path(
+    profile: &Profile,
+    mouse: &MouseSpec,
+    taken: &[String],
+    find: &mut impl FnMut(&DeviceFilter, &[PathBuf]) -> Option<PathBuf>,
+) -> Result<String, String> {
+    if let Some(DeviceSelector::Path(path)) = &mouse.device {
+        return Ok(path.to_string_lossy().to_string());
+    }
+    let taken: Vec<PathBuf> = taken.iter().map(PathBuf::from).collect();
+    match find(&profile.device_filter(mouse), &taken) {
+        Some(p) => {
+            let s = p.to_string_lossy().to_string();
+            log::info!("Auto-detected mouse: {}", s);
+            Ok(s)
+        }
+        None => match &mouse.device {
+            Some(DeviceSelector::Match(m)) => Err(format!(
+                "No mouse matches '{}'\n\
+                 Run `m2joy list-devices` to see why each device was skipped",
+                m
+            )),
+            _ => Err("No mouse device found. Are you in the 'input' group?\n\
+                      Try: sudo usermod -aG input $USER (then re-login)"
+                .into()),
+        },
+    }
+}
+
+/// Send a signal to the running m2joy instance.
+/// With `player`, the number travels as the signal's value (sigqueue).
+fn send_to_running(sig: libc::c_int, action: &str, player: Option<u32>) -> Result<(), String> {
     match find_running_instance() {
         Some(pid) => {
-            let ret = unsafe { libc::kill(pid, sig) };
-            if ret == 0 {
-                eprintln!("{} sent to m2joy (pid {})", action, pid);
-            } else {
-                eprintln!("Failed to send signal to m2joy (pid {})", pid);
-                std::process::exit(1);
+            let ret = match player {
+                Some(n) => unsafe {
+                    let value = libc::sigval {
+                        sival_ptr: n as usize as *mut libc::c_void,
+                    };
+                    libc::sigqueue(pid, sig, value)
+                },
+                None => unsafe { libc::kill(pid, sig) },
+            };
+            if ret != 0 {
+                return Err(format!("Failed to send signal to m2joy (pid {})", pid));
             }
+            match player {
+                Some(n) => eprintln!("{} for player {} sent to m2joy (pid {})", action, n, pid),
+                None => eprintln!("{} sent to m2joy (pid {})", action, pid),
+            }
+            Ok(())
         }
-        None => {
-            eprintln!("No running m2joy instance found");
-            std::process::exit(1);
-        }
+        None => Err("No running m2joy instance found".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_match::Verdict;
+    use crate::identity::IdentityConfig;
+    use crate::mouse::DeviceInfo;
+
+    #[test]
+    fn autoconfig_numbers_the_m2joy_pad() {
+        let identity = Identity::new(&IdentityConfig::default(), 1);
+        let expected = r#"# Written by m2joy, which updates it; remove this line to keep your edits
+input_driver = "udev"
+input_device = "m2joy Stick"
+input_device_display_name = "m2joy Stick"
+input_vendor_id = "4660"
+input_product_id = "22136"
+input_b_btn = "0"
+input_a_btn = "1"
+input_x_btn = "2"
+input_y_btn = "3"
+input_l_btn = "4"
+input_r_btn = "5"
+input_l2_btn = "6"
+input_r2_btn = "7"
+input_select_btn = "8"
+input_start_btn = "9"
+input_menu_toggle_btn = "10"
+input_l3_btn = "11"
+input_r3_btn = "12"
+input_up_btn = "h0up"
+input_down_btn = "h0down"
+input_left_btn = "h0left"
+input_right_btn = "h0right"
+input_l2_axis = "+2"
+input_r2_axis = "+5"
+input_l_x_plus_axis = "+0"
+input_l_x_minus_axis = "-0"
+input_l_y_plus_axis = "+1"
+input_l_y_minus_axis = "-1"
+input_r_x_plus_axis = "+3"
+input_r_x_minus_axis = "-3"
+input_r_y_plus_axis = "+4"
+input_r_y_minus_axis = "-4"
+input_b_btn_label = "A"
+input_a_btn_label = "B"
+input_x_btn_label = "X"
+input_y_btn_label = "Y"
+input_l_btn_label = "L1"
+input_r_btn_label = "R1"
+input_l2_btn_label = "L2 (Right Click)"
+input_r2_btn_label = "R2 (Left Click)"
+input_select_btn_label = "Select"
+input_start_btn_label = "Start"
+input_menu_toggle_btn_label = "Mode"
+input_l3_btn_label = "L3"
+input_r3_btn_label = "R3"
+input_up_btn_label = "D-Pad Up"
+input_down_btn_label = "D-Pad Down"
+input_left_btn_label = "D-Pad Left"
+input_right_btn_label = "D-Pad Right"
+input_l2_axis_label = "L2 (Analog)"
+input_r2_axis_label = "R2 (Analog)"
+input_l_x_plus_axis_label = "Left Analog Right"
+input_l_x_minus_axis_label = "Left Analog Left"
+input_l_y_plus_axis_label = "Left Analog Down"
+input_l_y_minus_axis_label = "Left Analog Up"
+input_r_x_plus_axis_label = "Right Analog Right"
+input_r_x_minus_axis_label = "Right Analog Left"
+input_r_y_plus_axis_label = "Right Analog Down"
+input_r_y_minus_axis_label = "Right Analog Up"
+"#;
+        assert_eq!(retroarch_autoconfig(&identity), expected);
+    }
+
+    #[test]
+    fn autoconfig_only_replaces_files_m2joy_wrote() {
+        let dir = std::env::temp_dir().join(format!("m2joy-autoconfig-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity = Identity::new(&IdentityConfig::default(), 1);
+        let path = dir.join("m2joy Stick.cfg");
+        let cfg = retroarch_autoconfig(&identity);
+        let write = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            write_retroarch_autoconfig(&dir, &identity);
+            std::fs::read_to_string(&path).unwrap()
+        };
+
+        std::fs::remove_file(&path).ok();
+        write_retroarch_autoconfig(&dir, &identity);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), cfg);
+        // Stale files m2joy wrote are brought up to date
+        let stale = format!("{}\ninput_b_btn = \"7\"\n", AUTOCONFIG_MARKER);
+        assert_eq!(write(&stale), cfg);
+        let old = "input_device = \"m2joy Stick\"\ninput_vendor_id = \"4660\"\n";
+        assert_eq!(write(old), cfg);
+        // Once the marker is gone the file is the user's
+        let edited = cfg.replacen(AUTOCONFIG_MARKER, "# mine", 1);
+        let edited = edited.replace("input_vendor_id = \"4660\"", "input_vendor_id = \"1\"");
+        assert_eq!(write(&edited), edited);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Nodes of a desk with two mice, a trackball, a keyboard and a pad.
+    const DESK: [(&str, &str, DeviceKind); 5] = [
+        ("/dev/input/event1", "Logitech G Pro", DeviceKind::Mouse),
+        ("/dev/input/event2", "AT Keyboard", DeviceKind::Keyboard),
+        ("/dev/input/event3", "Razer Viper", DeviceKind::Mouse),
+        (
+            "/dev/input/event4",
+            "Kensington Trackball",
+            DeviceKind::Mouse,
+        ),
+        ("/dev/input/event5", "Xbox Controller", DeviceKind::Gamepad),
+    ];
+
+    /// `find_device` over `DESK`.
+    fn find(filter: &DeviceFilter, taken: &[PathBuf]) -> Option<PathBuf> {
+        DESK.iter()
+            .map(|&(path, name, kind)| (PathBuf::from(path), name, kind))
+            .filter(|(path, _, _)| !taken.contains(path))
+            .find(|(path, name, kind)| {
+                let info = DeviceInfo {
+                    name: name.to_string(),
+                    vendor: 0,
+                    product: 0,
+                    phys: String::new(),
+                    uniq: String::new(),
+                };
+                filter.check(path, &info, *kind == filter.kind) == Verdict::Match
+            })
+            .map(|(path, _, _)| path)
+    }
+
+    fn players(profiles: &[&str]) -> Vec<PlayerSettings> {
+        profiles
+            .iter()
+            .map(|p| PlayerSettings {
+                profile_name: None,
+                profile: toml::from_str(p).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn players_take_the_next_free_devices() {
+        let players = players(&["[keyboard]", "[gamepad]"]);
+        let assigned = assign_devices(&players, find).unwrap();
+        assert_eq!(assigned[0].mice, ["/dev/input/event1"]);
+        assert_eq!(assigned[0].keyboard.as_deref(), Some("/dev/input/event2"));
+        assert_eq!(assigned[0].gamepad, None);
+        assert_eq!(assigned[1].mice, ["/dev/input/event3"]);
+        assert_eq!(assigned[1].keyboard, None);
+        assert_eq!(assigned[1].gamepad.as_deref(), Some("/dev/input/event5"));
+    }
+
+    #[test]
+    fn mice_entries_are_found_in_order() {
+        let players = players(&["[[mice]]\n\
+             device = \"name~=trackball\"\n\
+             [[mice]]\n\
+             device = \"/dev/input/event1\"\n\
+             [[mice]]"]);
+        let assigned = assign_devices(&players, find).unwrap();
+        // The path is taken as given, and skipped by auto-detection after it
+        assert_eq!(
+            assigned[0].mice,
+            [
+                "/dev/input/event4",
+                "/dev/input/event1",
+                "/dev/input/event3"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_devices_are_errors() {
+        let error = |profiles: &[&str]| match assign_devices(&players(profiles), find) {
+            Err(e) => e,
+            Ok(_) => panic!("{:?} found every device", profiles),
+        };
+        assert!(error(&["", "", "", ""]).starts_with("No mouse device found"));
+        assert!(error(&["device = \"name~=mx master\""]).starts_with("No mouse matches"));
+        assert!(error(&["[gamepad]", "[gamepad]"]).starts_with("No gamepad device found"));
     }
 }
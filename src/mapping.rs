@@ -0,0 +1,1075 @@
+use crate::gesture::Gestures;
+use crate::mouse::ButtonEvent;
+use crate::profile::{PadButton, Profile, Stick};
+use crate::virtual_pad::PadState;
+use evdev::Key;
+use serde::de::value::StrDeserializer;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Wheel movement per notch, in REL_WHEEL_HI_RES units.
+pub const WHEEL_NOTCH: i32 = 120;
+
+/// How wheel notches turn into button presses.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct WheelConfig {
+    /// How long each notch holds its output. At least one frame (~17ms at
+    /// 60 Hz) so the emulator's poll cannot miss it.
+    pub pulse_ms: f32,
+    /// Release between two pulses of the same input, so a quick flick
+    /// gives separate presses instead of one long one.
+    pub gap_ms: f32,
+    /// Notches waiting their turn beyond this are dropped, so a long spin
+    /// does not keep pressing after the wheel stopped.
+    pub max_queued: usize,
+}
+
+impl Default for WheelConfig {
+    fn default() -> Self {
+        Self {
+            pulse_ms: 50.0,
+            gap_ms: 34.0,
+            max_queued: 4,
+        }
+    }
+}
+
+impl WheelConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pulse_ms <= 0.0 || self.gap_ms <= 0.0 {
+            return Err("wheel pulse_ms and gap_ms must be > 0".into());
+        }
+        if self.max_queued == 0 {
+            return Err("wheel max_queued must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// A mouse button or wheel direction that can be mapped.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum Input {
+    Key(Key),
+    /// Vertical wheel as an axis, up positive.
+    Wheel,
+    /// Horizontal wheel as an axis, right positive.
+    HWheel,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// A short press (`left:tap`); the plain binding if not set.
+    Tap(Key),
+    /// Held past `hold_ms` (`left:hold`).
+    Hold(Key),
+    /// Second press of a double-click (`left:double`).
+    Double(Key),
+    /// Two buttons pressed together (`left+right`), lower code first.
+    Chord(Key, Key),
+}
+
+impl Input {
+    fn is_wheel(self) -> bool {
+        matches!(
+            self,
+            Input::Wheel
+                | Input::HWheel
+                | Input::WheelUp
+                | Input::WheelDown
+                | Input::WheelLeft
+                | Input::WheelRight
+        )
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::Key(Key::BTN_LEFT)
+    }
+}
+
+/// Short names for the usual mouse buttons; keyboard keys go by their
+/// evdev name with or without `KEY_` (`space`, `KEY_F12`), anything else by
+/// its evdev name (`BTN_TASK`).
+const BUTTON_NAMES: [(&str, Key); 8] = [
+    ("left", Key::BTN_LEFT),
+    ("right", Key::BTN_RIGHT),
+    ("middle", Key::BTN_MIDDLE),
+    ("side", Key::BTN_SIDE),
+    ("extra", Key::BTN_EXTRA),
+    ("forward", Key::BTN_FORWARD),
+    ("back", Key::BTN_BACK),
+    ("task", Key::BTN_TASK),
+];
+
+/// A single button by short or evdev name.
+fn button(s: &str) -> Result<Key, String> {
+    match BUTTON_NAMES.iter().find(|(name, _)| *name == s) {
+        Some(&(_, key)) => Ok(key),
+        None => Key::from_str(&s.to_uppercase())
+            .or_else(|_| Key::from_str(&format!("KEY_{}", s.to_uppercase())))
+            .map_err(|_| {
+                format!(
+                    "unknown input '{}' (expected left, right, middle, side, extra, \
+                     forward, back, task, wheel, hwheel, wheel-up/down/left/right, \
+                     a key like w or space, or an evdev name like BTN_TASK)",
+                    s
+                )
+            }),
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Some((a, b)) = s.split_once('+') {
+            let (a, b) = (button(a.trim())?, button(b.trim())?);
+            if a == b {
+                return Err(format!("'{}': a chord needs two different buttons", s));
+            }
+            return Ok(Input::Chord(a.min(b), a.max(b)));
+        }
+        if let Some((name, gesture)) = s.split_once(':') {
+            let key = button(name)?;
+            return match gesture {
+                "tap" => Ok(Input::Tap(key)),
+                "hold" => Ok(Input::Hold(key)),
+                "double" => Ok(Input::Double(key)),
+                _ => Err(format!(
+                    "'{}': unknown gesture '{}' (expected tap, hold or double)",
+                    s, gesture
+                )),
+            };
+        }
+        Ok(match s {
+            "wheel" => Input::Wheel,
+            "hwheel" => Input::HWheel,
+            "wheel-up" => Input::WheelUp,
+            "wheel-down" => Input::WheelDown,
+            "wheel-left" => Input::WheelLeft,
+            "wheel-right" => Input::WheelRight,
+            _ => Input::Key(button(s)?),
+        })
+    }
+}
+
+impl TryFrom<String> for Input {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |key: &Key| match BUTTON_NAMES.iter().find(|(_, k)| k == key) {
+            Some((name, _)) => name.to_string(),
+            None => format!("{:?}", key),
+        };
+        match self {
+            Input::Key(key) => f.write_str(&name(key)),
+            Input::Tap(key) => write!(f, "{}:tap", name(key)),
+            Input::Hold(key) => write!(f, "{}:hold", name(key)),
+            Input::Double(key) => write!(f, "{}:double", name(key)),
+            Input::Chord(a, b) => write!(f, "{}+{}", name(a), name(b)),
+            Input::Wheel => f.write_str("wheel"),
+            Input::HWheel => f.write_str("hwheel"),
+            Input::WheelUp => f.write_str("wheel-up"),
+            Input::WheelDown => f.write_str("wheel-down"),
+            Input::WheelLeft => f.write_str("wheel-left"),
+            Input::WheelRight => f.write_str("wheel-right"),
+        }
+    }
+}
+
+/// Things m2joy does itself instead of pressing something on the pad.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Release the mouse, like `m2joy toggle`.
+    ReleaseGrab,
+    /// Switch to the next profile in the config file, by name.
+    NextProfile,
+    SensitivityUp,
+    SensitivityDown,
+}
+
+/// The analog triggers.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    L2,
+    R2,
+}
+
+/// An axis the wheel can drive.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Axis {
+    L2,
+    R2,
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl Axis {
+    /// Triggers rest at 0 and go to 1; stick axes span -1 to 1.
+    fn min(self) -> f32 {
+        match self {
+            Axis::L2 | Axis::R2 => 0.0,
+            _ => -1.0,
+        }
+    }
+}
+
+/// What an input does while held.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "OutputConfig")]
+pub enum Output {
+    /// A pad button or D-pad direction.
+    Button(PadButton),
+    /// Hold an analog trigger at `value` (0-1) without the digital button.
+    Trigger(Trigger, f32),
+    /// Push a stick to (`x`, `y`), each -1 to 1 with +y down. Added to the
+    /// mouse motion on that stick.
+    Stick(Stick, f32, f32),
+    /// Run an action once, on press.
+    Action(Action),
+    /// Move an axis by `per_notch` for each wheel notch; it stays where the
+    /// wheel left it. Only for `wheel` and `hwheel`.
+    Axis(Axis, f32),
+}
+
+/// `output` as written in the file: a name, or a table for the analog kinds.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OutputConfig {
+    Name(String),
+    Trigger(TriggerOutput),
+    Stick(StickOutput),
+    Axis(AxisOutput),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggerOutput {
+    trigger: Trigger,
+    value: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StickOutput {
+    stick: Stick,
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AxisOutput {
+    axis: Axis,
+    per_notch: f32,
+}
+
+impl TryFrom<OutputConfig> for Output {
+    type Error = String;
+
+    fn try_from(config: OutputConfig) -> Result<Self, String> {
+        match config {
+            OutputConfig::Name(name) => by_name(&name)
+                .map(Output::Button)
+                .or_else(|| by_name(&name).map(Output::Action))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown output '{}' (expected a pad button, release-grab, \
+                         next-profile, sensitivity-up or sensitivity-down)",
+                        name
+                    )
+                }),
+            OutputConfig::Trigger(t) => Ok(Output::Trigger(t.trigger, t.value)),
+            OutputConfig::Stick(s) => Ok(Output::Stick(s.stick, s.x, s.y)),
+            OutputConfig::Axis(a) => Ok(Output::Axis(a.axis, a.per_notch)),
+        }
+    }
+}
+
+/// A tap without its own binding does what the button does.
+fn add_taps(bindings: &mut HashMap<Input, Binding>, gestures: &Gestures) {
+    let taps: Vec<_> = bindings
+        .iter()
+        .filter_map(|(input, b)| match *input {
+            Input::Key(k) if gestures.gestured(k) => Some((Input::Tap(k), *b)),
+            _ => None,
+        })
+        .collect();
+    for (tap, binding) in taps {
+        bindings.entry(tap).or_insert(binding);
+    }
+}
+
+/// A unit variant by its name in the config file.
+fn by_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    let de: StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+    T::deserialize(de).ok()
+}
+
+impl Output {
+    pub fn validate(&self, input: Input) -> Result<(), String> {
+        let wheel_axis = matches!(input, Input::Wheel | Input::HWheel);
+        match *self {
+            Output::Axis(..) if !wheel_axis => Err("axis outputs need wheel or hwheel".into()),
+            _ if wheel_axis && !matches!(self, Output::Axis(..)) => {
+                Err("wheel and hwheel only drive axes; use wheel-up/down for presses".into())
+            }
+            Output::Trigger(_, v) if !(0.0..=1.0).contains(&v) => {
+                Err("trigger value must be 0-1".into())
+            }
+            Output::Stick(_, x, y) if !(-1.0..=1.0).contains(&x) || !(-1.0..=1.0).contains(&y) => {
+                Err("stick x and y must be -1 to 1".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Autofire while an input is active.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Turbo {
+    /// Presses per second.
+    pub rate: f32,
+    /// Fraction of each cycle spent pressed.
+    pub duty: f32,
+    /// The emulator's frame rate. Cycles are whole frames, so every press
+    /// and every release lasts at least one.
+    pub fps: f32,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self {
+            rate: 10.0,
+            duty: 0.5,
+            fps: 60.0,
+        }
+    }
+}
+
+impl Turbo {
+    fn validate(&self) -> Result<(), String> {
+        if self.rate <= 0.0 || self.fps <= 0.0 {
+            return Err("turbo rate and fps must be > 0".into());
+        }
+        if self.rate * 2.0 > self.fps {
+            return Err("turbo rate can be at most half of fps".into());
+        }
+        if self.duty <= 0.0 || self.duty >= 1.0 {
+            return Err("turbo duty must be between 0 and 1".into());
+        }
+        Ok(())
+    }
+
+    /// Whether autofire is pressed `elapsed` after the input became active.
+    /// Each cycle starts pressed, so the first press is immediate.
+    fn pressed(&self, elapsed: Duration) -> bool {
+        let cycle = (self.fps / self.rate).round().max(2.0) as u64;
+        let on = ((cycle as f32 * self.duty).round() as u64).clamp(1, cycle - 1);
+        let frame = (elapsed.as_secs_f64() * self.fps as f64) as u64;
+        frame % cycle < on
+    }
+}
+
+/// An output with its modifiers.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "toml::Value")]
+pub struct Binding {
+    pub output: Output,
+    pub turbo: Option<Turbo>,
+    /// One press turns the output on, the next turns it off.
+    pub toggle: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModifiedBinding {
+    output: Output,
+    turbo: Option<Turbo>,
+    #[serde(default)]
+    toggle: bool,
+}
+
+/// A bare output, or a table with `output` and modifiers. Told apart by
+/// the `output` key so errors point at the right form.
+impl TryFrom<toml::Value> for Binding {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, String> {
+        if value.get("output").is_some() {
+            let m = ModifiedBinding::deserialize(value).map_err(|e| e.message().to_string())?;
+            Ok(Self {
+                output: m.output,
+                turbo: m.turbo,
+                toggle: m.toggle,
+            })
+        } else {
+            Output::deserialize(value)
+                .map(Self::from)
+                .map_err(|e| e.message().to_string())
+        }
+    }
+}
+
+impl From<Output> for Binding {
+    fn from(output: Output) -> Self {
+        Self {
+            output,
+            turbo: None,
+            toggle: false,
+        }
+    }
+}
+
+impl Binding {
+    pub fn validate(&self, input: Input) -> Result<(), String> {
+        self.output.validate(input)?;
+        if self.turbo.is_some() || self.toggle {
+            if input.is_wheel() {
+                return Err("turbo and toggle need a button, not the wheel".into());
+            }
+            if matches!(self.output, Output::Action(_) | Output::Axis(..)) {
+                return Err("turbo and toggle need a button, trigger or stick output".into());
+            }
+        }
+        self.turbo.as_ref().map_or(Ok(()), Turbo::validate)
+    }
+}
+
+/// A set of bindings on top of the profile's `map`, active while its
+/// modifier button is held or after it is toggled on.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LayerConfig {
+    pub modifier: Input,
+    /// One press turns the layer on, the next turns it off.
+    #[serde(default)]
+    pub toggle: bool,
+    /// Send every mouse's motion to this stick while active.
+    pub stick: Option<Stick>,
+    #[serde(default)]
+    pub map: HashMap<Input, Binding>,
+}
+
+impl LayerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !matches!(self.modifier, Input::Key(_)) {
+            return Err(format!(
+                "modifier '{}' must be a single button",
+                self.modifier
+            ));
+        }
+        for (input, binding) in &self.map {
+            binding
+                .validate(*input)
+                .map_err(|e| format!("map.{}: {}", input, e))?;
+        }
+        Ok(())
+    }
+}
+
+struct Layer {
+    name: String,
+    modifier: Input,
+    toggle: bool,
+    stick: Option<Stick>,
+    bindings: HashMap<Input, Binding>,
+}
+
+/// What the mapped inputs add up to at one instant.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MappedState {
+    pub pad: PadState,
+    /// Stick offsets as a fraction of full, indexed by `Stick as usize`.
+    pub sticks: [(f32, f32); 2],
+}
+
+/// A wheel notch's press, on the events' clock.
+struct Pulse {
+    input: Input,
+    start: Duration,
+    end: Duration,
+}
+
+/// Turns button and wheel events into pad output through a profile's
+/// bindings.
+pub struct Mapper {
+    bindings: HashMap<Input, Binding>,
+    /// In name order.
+    layers: Vec<Layer>,
+    /// Indices into `layers`, in the order they came on; the last wins.
+    active_layers: Vec<usize>,
+    layer_changed: bool,
+    gestures: Gestures,
+    /// Scratch for events coming out of `gestures`.
+    synthetic: Vec<ButtonEvent>,
+    wheel: WheelConfig,
+    /// Inputs held down, once per mouse holding them, with the press time.
+    held: Vec<(Input, Duration)>,
+    /// Toggle bindings switched on, with the time they were.
+    toggled: Vec<(Input, Duration)>,
+    /// Wheel notches pressing or waiting to, in start order per input.
+    pulses: Vec<Pulse>,
+    /// Wheel movement short of a notch: vertical, horizontal.
+    partial: [i32; 2],
+    /// Positions of wheel-driven axes, indexed by `Axis as usize`.
+    axes: [f32; 6],
+}
+
+impl Mapper {
+    pub fn new(profile: &Profile) -> Self {
+        let mut bindings = profile.bindings();
+        let mut layers: Vec<Layer> = profile
+            .layers
+            .iter()
+            .map(|(name, l)| Layer {
+                name: name.clone(),
+                modifier: l.modifier,
+                toggle: l.toggle,
+                stick: l.stick,
+                bindings: l.map.clone(),
+            })
+            .collect();
+        layers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // Gestures are detected the same way in every layer
+        let inputs = bindings
+            .keys()
+            .chain(layers.iter().flat_map(|l| l.bindings.keys()));
+        let gestures = Gestures::new(inputs.copied(), &profile.gestures);
+        for map in std::iter::once(&mut bindings).chain(layers.iter_mut().map(|l| &mut l.bindings))
+        {
+            add_taps(map, &gestures);
+        }
+        Self {
+            bindings,
+            layers,
+            active_layers: Vec::new(),
+            layer_changed: false,
+            gestures,
+            synthetic: Vec::new(),
+            wheel: profile.wheel,
+            held: Vec::new(),
+            toggled: Vec::new(),
+            pulses: Vec::new(),
+            partial: [0; 2],
+            axes: [0.0; 6],
+        }
+    }
+
+    /// Binding for an input: the newest active layer that has one, or the
+    /// profile's.
+    fn binding(&self, input: &Input) -> Option<&Binding> {
+        self.active_layers
+            .iter()
+            .rev()
+            .find_map(|&i| self.layers[i].bindings.get(input))
+            .or_else(|| self.bindings.get(input))
+    }
+
+    /// Name of the layer in effect, if any.
+    pub fn layer(&self) -> Option<&str> {
+        self.active_layers
+            .last()
+            .map(|&i| self.layers[i].name.as_str())
+    }
+
+    /// Stick the active layers send mouse motion to, if any does.
+    pub fn stick(&self) -> Option<Stick> {
+        self.active_layers
+            .iter()
+            .rev()
+            .find_map(|&i| self.layers[i].stick)
+    }
+
+    /// Whether the layer changed since the last call.
+    pub fn take_layer_change(&mut self) -> bool {
+        std::mem::take(&mut self.layer_changed)
+    }
+
+    /// Turn layers on or off for a modifier press or release. Returns
+    /// whether the button is a modifier, which then does nothing else.
+    fn modifier(&mut self, event: &ButtonEvent) -> bool {
+        let mut found = false;
+        for i in 0..self.layers.len() {
+            if self.layers[i].modifier != event.input {
+                continue;
+            }
+            found = true;
+            let on = self.active_layers.iter().position(|&a| a == i);
+            let layer = &self.layers[i];
+            match (event.value != 0, on) {
+                (true, None) => self.active_layers.push(i),
+                (true, Some(pos)) if layer.toggle => {
+                    self.active_layers.remove(pos);
+                }
+                (false, Some(pos)) if !layer.toggle => {
+                    self.active_layers.remove(pos);
+                }
+                _ => continue,
+            }
+            self.layer_changed = true;
+        }
+        found
+    }
+
+    /// Take another profile's bindings, letting go of everything.
+    pub fn set_profile(&mut self, profile: &Profile) {
+        *self = Self::new(profile);
+    }
+
+    /// Apply one event, adding the actions it triggers. Buttons with tap,
+    /// hold, double or chord bindings go through gesture detection first.
+    pub fn feed(&mut self, event: &ButtonEvent, actions: &mut Vec<Action>) {
+        if self.modifier(event) {
+            return;
+        }
+        match event.input {
+            Input::Key(k) if self.gestures.handles(k) => {
+                let mut out = std::mem::take(&mut self.synthetic);
+                self.gestures.feed(event, &mut out);
+                self.apply_all(&mut out, actions);
+                self.synthetic = out;
+            }
+            _ => self.apply(event, actions),
+        }
+    }
+
+    /// Settle gestures whose time ran out by `now`.
+    pub fn poll(&mut self, now: Duration, actions: &mut Vec<Action>) {
+        let mut out = std::mem::take(&mut self.synthetic);
+        self.gestures.poll(now, &mut out);
+        self.apply_all(&mut out, actions);
+        self.synthetic = out;
+    }
+
+    fn apply_all(&mut self, events: &mut Vec<ButtonEvent>, actions: &mut Vec<Action>) {
+        for event in events.drain(..) {
+            self.apply(&event, actions);
+        }
+    }
+
+    fn apply(&mut self, event: &ButtonEvent, actions: &mut Vec<Action>) {
+        match event.input {
+            Input::Wheel | Input::HWheel => self.wheel(event, actions),
+            // Only produced here, from `Wheel` and `HWheel`
+            Input::WheelUp | Input::WheelDown | Input::WheelLeft | Input::WheelRight => {}
+            _ if event.value == 0 => {
+                if let Some(i) = self.held.iter().position(|h| h.0 == event.input) {
+                    self.held.swap_remove(i);
+                }
+            }
+            _ => {
+                self.held.push((event.input, event.time));
+                if self.binding(&event.input).is_some_and(|b| b.toggle) {
+                    match self.toggled.iter().position(|t| t.0 == event.input) {
+                        Some(i) => {
+                            self.toggled.swap_remove(i);
+                        }
+                        None => self.toggled.push((event.input, event.time)),
+                    }
+                }
+                self.action(event.input, actions);
+            }
+        }
+    }
+
+    fn action(&self, input: Input, actions: &mut Vec<Action>) {
+        if let Some(Output::Action(action)) = self.binding(&input).map(|b| b.output) {
+            actions.push(action);
+        }
+    }
+
+    /// Wheel movement in hi-res units: drive a bound axis directly, and
+    /// queue one pulse per whole notch in either direction.
+    fn wheel(&mut self, event: &ButtonEvent, actions: &mut Vec<Action>) {
+        let vertical = event.input == Input::Wheel;
+        if let Some(Output::Axis(axis, per_notch)) = self.binding(&event.input).map(|b| b.output) {
+            let value = &mut self.axes[axis as usize];
+            *value = (*value + per_notch * event.value as f32 / WHEEL_NOTCH as f32)
+                .clamp(axis.min(), 1.0);
+        }
+
+        // Turning back starts a fresh notch
+        let partial = &mut self.partial[!vertical as usize];
+        if (*partial > 0) != (event.value > 0) {
+            *partial = 0;
+        }
+        *partial += event.value;
+        let notches = *partial / WHEEL_NOTCH;
+        *partial %= WHEEL_NOTCH;
+
+        let input = match (vertical, notches > 0) {
+            (true, true) => Input::WheelUp,
+            (true, false) => Input::WheelDown,
+            (false, true) => Input::WheelRight,
+            (false, false) => Input::WheelLeft,
+        };
+        if self.binding(&input).is_none() {
+            return;
+        }
+        let pulse = Duration::from_secs_f32(self.wheel.pulse_ms / 1000.0);
+        let gap = Duration::from_secs_f32(self.wheel.gap_ms / 1000.0);
+        for _ in 0..notches.abs() {
+            let queued = self.pulses.iter().filter(|p| p.input == input);
+            if queued.clone().count() >= self.wheel.max_queued {
+                break;
+            }
+            let start = queued
+                .map(|p| p.end + gap)
+                .max()
+                .map_or(event.time, |t| t.max(event.time));
+            self.pulses.push(Pulse {
+                input,
+                start,
+                end: start + pulse,
+            });
+            self.action(input, actions);
+        }
+    }
+
+    /// Output of everything held at `now` (same clock as the events).
+    pub fn state(&mut self, now: Duration) -> MappedState {
+        self.pulses.retain(|p| p.end > now);
+        let mut state = MappedState::default();
+        // Toggle bindings follow their switch instead of the button
+        let held = self
+            .held
+            .iter()
+            .filter(|(i, _)| !self.binding(i).is_some_and(|b| b.toggle));
+        let pulsing = self
+            .pulses
+            .iter()
+            .filter(|p| p.start <= now)
+            .map(|p| (p.input, p.start));
+        for (input, since) in held.chain(&self.toggled).copied().chain(pulsing) {
+            let Some(binding) = self.binding(&input) else {
+                continue;
+            };
+            if binding
+                .turbo
+                .is_some_and(|t| !t.pressed(now.saturating_sub(since)))
+            {
+                continue;
+            }
+            match binding.output {
+                Output::Button(b) => state.pad.press(b),
+                Output::Trigger(t, v) => {
+                    let slot = &mut state.pad.triggers[t as usize];
+                    *slot = slot.max(v);
+                }
+                Output::Stick(s, x, y) => {
+                    let offset = &mut state.sticks[s as usize];
+                    offset.0 += x;
+                    offset.1 += y;
+                }
+                Output::Action(_) | Output::Axis(..) => {}
+            }
+        }
+
+        let [l2, r2, left_x, left_y, right_x, right_y] = self.axes;
+        state.pad.triggers[0] = state.pad.triggers[0].max(l2);
+        state.pad.triggers[1] = state.pad.triggers[1].max(r2);
+        state.sticks[0].0 += left_x;
+        state.sticks[0].1 += left_y;
+        state.sticks[1].0 += right_x;
+        state.sticks[1].1 += right_y;
+        state
+    }
+
+    /// Forget everything held, e.g. when the mouse is released. Wheel axes
+    /// go back to rest too.
+    pub fn reset(&mut self) {
+        self.gestures.reset();
+        if !self.active_layers.is_empty() {
+            self.active_layers.clear();
+            self.layer_changed = true;
+        }
+        self.held.clear();
+        self.toggled.clear();
+        self.pulses.clear();
+        self.partial = [0; 2];
+        self.axes = [0.0; 6];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A validated profile with `map` as its `[map]` table.
+    fn profile(map: &str) -> Result<Profile, String> {
+        let profile: Profile =
+            toml::from_str(&format!("[map]\n{}", map)).map_err(|e| e.message().to_string())?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// What `input` is bound to in `map`.
+    fn output(map: &str, input: &str) -> Output {
+        profile(map).unwrap().bindings()[&input.parse().unwrap()].output
+    }
+
+    #[test]
+    fn outputs_parse_from_names_and_tables() {
+        assert_eq!(
+            output(r#"left = "south""#, "left"),
+            Output::Button(PadButton::South)
+        );
+        assert_eq!(
+            output(r#"middle = "release-grab""#, "middle"),
+            Output::Action(Action::ReleaseGrab)
+        );
+        assert_eq!(
+            output(r#"side = { trigger = "l2", value = 0.5 }"#, "side"),
+            Output::Trigger(Trigger::L2, 0.5)
+        );
+        assert_eq!(
+            output(r#"extra = { stick = "left", x = 0.0, y = -1.0 }"#, "extra"),
+            Output::Stick(Stick::Left, 0.0, -1.0)
+        );
+        assert_eq!(
+            output(r#""wheel-up" = "north""#, "wheel-up"),
+            Output::Button(PadButton::North)
+        );
+        // `buttons` still binds the clicks `map` leaves alone
+        assert_eq!(
+            output(r#"middle = "south""#, "right"),
+            Output::Button(PadButton::L2)
+        );
+    }
+
+    #[test]
+    fn bad_bindings_are_rejected() {
+        for map in [
+            r#"bogus = "south""#,
+            r#"left = "jump""#,
+            r#"left = { trigger = "l2", value = 1.5 }"#,
+            r#"left = { stick = "right", x = 2.0, y = 0.0 }"#,
+            r#"left = { trigger = "l2", value = 1.0, extra = 1 }"#,
+            r#"left = 3"#,
+        ] {
+            assert!(profile(map).is_err(), "{}", map);
+        }
+    }
+
+    #[test]
+    fn inputs_display_as_they_parse() {
+        for name in [
+            "left",
+            "side",
+            "BTN_TRIGGER_HAPPY1",
+            "wheel-up",
+            "wheel-right",
+        ] {
+            let input: Input = name.parse().unwrap();
+            assert_eq!(input.to_string(), name);
+        }
+    }
+
+    fn mapper(map: &str) -> Mapper {
+        Mapper::new(&profile(map).unwrap())
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Wheel movement of `value` hi-res units at `t` ms, and the actions it
+    /// runs.
+    fn wheel(m: &mut Mapper, input: Input, value: i32, t: u64) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let event = ButtonEvent {
+            time: ms(t),
+            input,
+            value,
+        };
+        m.feed(&event, &mut actions);
+        actions
+    }
+
+    fn pressed(m: &mut Mapper, button: PadButton, t: u64) -> bool {
+        m.state(ms(t)).pad.pressed(button)
+    }
+
+    #[test]
+    fn notches_queue_pulses_with_gaps() {
+        let mut m = mapper(r#""wheel-up" = "north""#);
+        wheel(&mut m, Input::Wheel, 3 * WHEEL_NOTCH, 0);
+        // 50ms pulses 34ms apart
+        let times = [0, 45, 55, 80, 90, 130, 140, 165, 175, 215, 225, 400];
+        let pattern: Vec<bool> = times
+            .iter()
+            .map(|&t| pressed(&mut m, PadButton::North, t))
+            .collect();
+        let expected = [
+            true, true, false, false, true, true, false, false, true, true, false, false,
+        ];
+        assert_eq!(pattern, expected);
+    }
+
+    #[test]
+    fn queue_is_capped_at_max_queued() {
+        let mut m = mapper(r#""wheel-down" = "next-profile""#);
+        let actions = wheel(&mut m, Input::Wheel, -10 * WHEEL_NOTCH, 0);
+        assert_eq!(actions, [Action::NextProfile; 4]);
+        let mut m = mapper(r#""wheel-left" = "west""#);
+        wheel(&mut m, Input::HWheel, -10 * WHEEL_NOTCH, 0);
+        assert!(pressed(&mut m, PadButton::West, 3 * 84 + 25));
+        assert!(!pressed(&mut m, PadButton::West, 4 * 84 + 25));
+    }
+
+    #[test]
+    fn partial_notches_add_up_until_the_direction_changes() {
+        let mut m = mapper(r#""wheel-up" = "north""#);
+        wheel(&mut m, Input::Wheel, 60, 0);
+        assert!(!pressed(&mut m, PadButton::North, 1));
+        wheel(&mut m, Input::Wheel, 60, 10);
+        assert!(pressed(&mut m, PadButton::North, 10));
+
+        let mut m = mapper(r#""wheel-up" = "north""#);
+        wheel(&mut m, Input::Wheel, 90, 0);
+        wheel(&mut m, Input::Wheel, -30, 10);
+        wheel(&mut m, Input::Wheel, 60, 20);
+        assert!(!pressed(&mut m, PadButton::North, 20));
+        // Unbound directions send nothing
+        assert!(!pressed(&mut m, PadButton::South, 20));
+    }
+
+    #[test]
+    fn wheel_axis_stays_where_the_wheel_left_it() {
+        let mut m = mapper(r#"wheel = { axis = "l2", per_notch = 0.25 }"#);
+        wheel(&mut m, Input::Wheel, 2 * WHEEL_NOTCH, 0);
+        assert_eq!(m.state(ms(1000)).pad.triggers[0], 0.5);
+        wheel(&mut m, Input::Wheel, -WHEEL_NOTCH / 2, 1000);
+        assert_eq!(m.state(ms(1000)).pad.triggers[0], 0.375);
+        wheel(&mut m, Input::Wheel, -5 * WHEEL_NOTCH, 1000);
+        assert_eq!(m.state(ms(1000)).pad.triggers[0], 0.0);
+        m.reset();
+        wheel(&mut m, Input::Wheel, WHEEL_NOTCH, 2000);
+        assert_eq!(m.state(ms(2000)).pad.triggers[0], 0.25);
+    }
+
+    fn click(m: &mut Mapper, key: Key, value: i32, t: u64) {
+        let event = ButtonEvent {
+            time: ms(t),
+            input: Input::Key(key),
+            value,
+        };
+        m.feed(&event, &mut Vec::new());
+    }
+
+    fn south(m: &mut Mapper, t: u64) -> bool {
+        m.state(ms(t)).pad.pressed(PadButton::South)
+    }
+
+    #[test]
+    fn turbo_pulses_in_whole_frames_while_held() {
+        let mut m =
+            mapper(r#"side = { output = "south", turbo = { rate = 10, duty = 0.5, fps = 60 } }"#);
+        assert!(!south(&mut m, 0));
+        click(&mut m, Key::BTN_SIDE, 1, 1000);
+        // Three frames of 16.7ms pressed, three released
+        let pattern: Vec<bool> = (0..12).map(|f| south(&mut m, 1008 + f * 50 / 3)).collect();
+        let cycle = [true, true, true, false, false, false];
+        assert_eq!(pattern, [cycle, cycle].concat());
+        click(&mut m, Key::BTN_SIDE, 0, 1200);
+        assert!(!south(&mut m, 1201));
+        // The next hold starts a fresh cycle, pressed at once
+        click(&mut m, Key::BTN_SIDE, 1, 1255);
+        assert!(south(&mut m, 1255));
+    }
+
+    #[test]
+    fn toggle_switches_on_press_and_ignores_release() {
+        let mut m = mapper(r#"side = { output = "south", toggle = true }"#);
+        click(&mut m, Key::BTN_SIDE, 1, 0);
+        assert!(south(&mut m, 10));
+        click(&mut m, Key::BTN_SIDE, 0, 20);
+        assert!(south(&mut m, 500));
+        click(&mut m, Key::BTN_SIDE, 1, 600);
+        assert!(!south(&mut m, 610));
+        click(&mut m, Key::BTN_SIDE, 0, 620);
+        assert!(!south(&mut m, 700));
+        click(&mut m, Key::BTN_SIDE, 1, 800);
+        assert!(south(&mut m, 810));
+        m.reset();
+        assert!(!south(&mut m, 820));
+    }
+
+    #[test]
+    fn toggled_turbo_keeps_pulsing_after_release() {
+        let mut m = mapper(
+            r#"side = { output = "south", toggle = true, turbo = { rate = 15, duty = 0.5, fps = 60 } }"#,
+        );
+        click(&mut m, Key::BTN_SIDE, 1, 0);
+        click(&mut m, Key::BTN_SIDE, 0, 10);
+        // Two frames on, two off, timed from the toggle
+        let pattern: Vec<bool> = (0..8).map(|f| south(&mut m, 8 + f * 50 / 3)).collect();
+        assert_eq!(
+            pattern,
+            [true, true, false, false, true, true, false, false]
+        );
+        click(&mut m, Key::BTN_SIDE, 1, 200);
+        assert!(!south(&mut m, 200));
+        assert!(!south(&mut m, 210));
+    }
+
+    #[test]
+    fn layer_modifier_only_switches_the_layer() {
+        let mut m = mapper(
+            "side = \"south\"\n\
+             [layers.aim]\nmodifier = \"side\"\nstick = \"left\"\nmap = { left = \"north\" }",
+        );
+        click(&mut m, Key::BTN_SIDE, 1, 0);
+        assert_eq!(m.layer(), Some("aim"));
+        assert!(m.take_layer_change());
+        assert_eq!(m.stick(), Some(Stick::Left));
+        // The modifier's own binding is not pressed
+        assert!(!south(&mut m, 10));
+        click(&mut m, Key::BTN_LEFT, 1, 20);
+        let state = m.state(ms(30));
+        assert!(state.pad.pressed(PadButton::North));
+        assert!(!state.pad.pressed(PadButton::R2));
+        // Buttons the layer leaves alone keep their usual output
+        click(&mut m, Key::BTN_RIGHT, 1, 40);
+        assert!(m.state(ms(50)).pad.pressed(PadButton::L2));
+
+        click(&mut m, Key::BTN_LEFT, 0, 60);
+        click(&mut m, Key::BTN_SIDE, 0, 70);
+        assert_eq!(m.layer(), None);
+        click(&mut m, Key::BTN_LEFT, 1, 80);
+        assert!(m.state(ms(90)).pad.pressed(PadButton::R2));
+    }
+
+    #[test]
+    fn toggled_layer_stays_on_until_the_next_press() {
+        let mut m = mapper("[layers.aim]\nmodifier = \"side\"\ntoggle = true");
+        click(&mut m, Key::BTN_SIDE, 1, 0);
+        click(&mut m, Key::BTN_SIDE, 0, 10);
+        assert_eq!(m.layer(), Some("aim"));
+        click(&mut m, Key::BTN_SIDE, 1, 20);
+        assert_eq!(m.layer(), None);
+        click(&mut m, Key::BTN_SIDE, 0, 30);
+        assert_eq!(m.layer(), None);
+    }
+}
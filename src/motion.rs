@@ -0,0 +1,197 @@
+use crate::identity::Identity;
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, InputId, MiscType, PropType,
+    UinputAbsSetup,
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Accelerometer counts per g, as hid-playstation reports them.
+const ACCEL_RES: i32 = 8192;
+const ACCEL_RANGE: i32 = 4 * ACCEL_RES;
+
+/// Gyro counts per degree per second, as hid-playstation reports them.
+const GYRO_RES: i32 = 1024;
+/// Far wider than the drivers' ±2048 deg/s: a flick's counts within one
+/// tick go well past that, and clipping them would lose angle.
+const GYRO_RANGE: i32 = 65536 * GYRO_RES;
+
+/// The mouse as a gyro: a motion sensor device next to the pad that games
+/// aiming with motion (Dolphin, Cemu, Switch emulators) read, so the mouse
+/// turns the view by a fixed angle per count instead of through a stick.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotionConfig {
+    /// Rotation per mouse count.
+    pub degrees_per_count: f32,
+    /// Also move the stick, as without `motion`.
+    pub stick: bool,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            degrees_per_count: 0.05,
+            stick: false,
+        }
+    }
+}
+
+impl MotionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        // Written to reject NaN too
+        if !(self.degrees_per_count > 0.0 && self.degrees_per_count.is_finite()) {
+            return Err(format!(
+                "motion.degrees_per_count must be a number > 0, got {}",
+                self.degrees_per_count
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The pad's motion sensors, like the separate device hid-playstation and
+/// hid-nintendo create: an accelerometer on ABS_X/Y/Z, a gyro on
+/// ABS_RX/RY/RZ and a timestamp on every report.
+pub struct MotionSensor {
+    device: VirtualDevice,
+    config: MotionConfig,
+    /// Time of the last report, None at rest.
+    last: Option<Duration>,
+}
+
+impl MotionSensor {
+    pub fn new(identity: &Identity, config: &MotionConfig) -> std::io::Result<Self> {
+        let axis = |axis, range: i32, res| {
+            UinputAbsSetup::new(axis, AbsInfo::new(0, -range, range, 16, 0, res))
+        };
+        let accel = |a| axis(a, ACCEL_RANGE, ACCEL_RES);
+        let gyro = |a| axis(a, GYRO_RANGE, GYRO_RES);
+
+        let mut props = AttributeSet::<PropType>::new();
+        props.insert(PropType::ACCELEROMETER);
+        let mut misc = AttributeSet::<MiscType>::new();
+        misc.insert(MiscType::MSC_TIMESTAMP);
+
+        let name = format!("{} Motion Sensors", identity.name);
+        let device = VirtualDeviceBuilder::new()?
+            .name(&name)
+            .input_id(InputId::new(
+                identity.bus,
+                identity.vendor,
+                identity.product,
+                identity.version,
+            ))
+            .with_properties(&props)?
+            .with_msc(&misc)?
+            .with_absolute_axis(&accel(AbsoluteAxisType::ABS_X))?
+            .with_absolute_axis(&accel(AbsoluteAxisType::ABS_Y))?
+            .with_absolute_axis(&accel(AbsoluteAxisType::ABS_Z))?
+            .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RX))?
+            .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RY))?
+            .with_absolute_axis(&gyro(AbsoluteAxisType::ABS_RZ))?
+            .build()?;
+
+        log::info!("Created motion sensors '{}'", name);
+
+        Ok(Self {
+            device,
+            config: config.clone(),
+            last: None,
+        })
+    }
+
+    /// Whether the mouse still moves the stick too.
+    pub fn stick(&self) -> bool {
+        self.config.stick
+    }
+
+    /// Report the rotation of `counts` mouse counts (y down) since the last
+    /// report, at `now` (CLOCK_MONOTONIC). Games integrate the rate over
+    /// the timestamps, so the first report after rest only sets the start.
+    pub fn update(&mut self, counts: (i32, i32), now: Duration) -> std::io::Result<()> {
+        let Some(last) = self.last.replace(now) else {
+            return self.report((0, 0), now);
+        };
+        let dt = (now - last).as_secs_f32();
+        if dt <= 0.0 {
+            return Ok(());
+        }
+        self.report(rates(counts, self.config.degrees_per_count, dt), now)
+    }
+
+    /// Hold still, e.g. while released.
+    pub fn rest(&mut self) -> std::io::Result<()> {
+        match self.last.take() {
+            Some(_) => self.report((0, 0), crate::mouse::monotonic_now()),
+            None => Ok(()),
+        }
+    }
+
+    /// One report: pitch and yaw rates, held level with gravity pulling
+    /// straight down.
+    fn report(&mut self, (pitch, yaw): (i32, i32), now: Duration) -> std::io::Result<()> {
+        let abs =
+            |axis: AbsoluteAxisType, value| InputEvent::new_now(EventType::ABSOLUTE, axis.0, value);
+        // Microseconds, wrapping like the drivers' counters
+        let timestamp = now.as_micros() as u32 as i32;
+        self.device.emit(&[
+            abs(AbsoluteAxisType::ABS_X, 0),
+            abs(AbsoluteAxisType::ABS_Y, ACCEL_RES),
+            abs(AbsoluteAxisType::ABS_Z, 0),
+            abs(AbsoluteAxisType::ABS_RX, pitch),
+            abs(AbsoluteAxisType::ABS_RY, yaw),
+            abs(AbsoluteAxisType::ABS_RZ, 0),
+            InputEvent::new_now(EventType::MISC, MiscType::MSC_TIMESTAMP.0, timestamp),
+            InputEvent::new_now(EventType::SYNCHRONIZATION, 0, 0),
+        ])
+    }
+}
+
+/// Pitch and yaw rates, in gyro counts, for `counts` mouse counts over `dt`
+/// seconds.
+fn rates(counts: (i32, i32), degrees_per_count: f32, dt: f32) -> (i32, i32) {
+    let rate = |c: i32| {
+        let deg_per_s = c as f32 * degrees_per_count / dt;
+        ((deg_per_s * GYRO_RES as f32).round() as i32).clamp(-GYRO_RANGE, GYRO_RANGE)
+    };
+    // Right turns the view right (yaw is counter-clockwise seen from above)
+    // and up tilts it up (pitch)
+    (rate(-counts.1), rate(-counts.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrees_per_count_must_be_positive() {
+        for bad in [0.0, -0.05, f32::NAN, f32::INFINITY] {
+            let config = MotionConfig {
+                degrees_per_count: bad,
+                ..MotionConfig::default()
+            };
+            assert!(config.validate().is_err(), "{}", bad);
+        }
+        assert!(MotionConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rate_is_the_angle_over_the_interval() {
+        // 10 counts right at 0.05 deg/count over 4ms: 125 deg/s of yaw
+        assert_eq!(rates((10, 0), 0.05, 0.004), (0, -125 * GYRO_RES));
+        // The same angle over twice the time is half the rate
+        assert_eq!(rates((10, 0), 0.05, 0.008), (0, -125 * GYRO_RES / 2));
+        // Mouse up (negative y) pitches up
+        assert_eq!(rates((0, -4), 0.05, 0.001), (200 * GYRO_RES, 0));
+    }
+
+    #[test]
+    fn rate_is_clamped_to_the_axis_range() {
+        assert_eq!(
+            rates((-100_000, 100_000), 1.0, 0.001),
+            (-GYRO_RANGE, GYRO_RANGE)
+        );
+    }
+}
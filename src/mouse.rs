@@ -1,86 +1,345 @@
-use evdev::{Device, InputEventKind, Key, RelativeAxisType};
+use crate::device_match::{DeviceFilter, DeviceKind, Verdict};
+use crate::ff::{FfEffects, FfReceiver};
+use crate::gamepad::{is_gamepad, AxisRanges, PadAxes};
+use crate::hotplug::{self, DeviceWatcher};
+use crate::mapping::{Input, WHEEL_NOTCH};
+use crate::ring::Producer;
+use evdev::{
+    AttributeSet, Device, FFEffectType, InputEventKind, Key, RelativeAxisType, Synchronization,
+};
+use std::os::fd::AsRawFd;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
-pub struct MouseState {
-    pub dx: AtomicI32,
-    pub dy: AtomicI32,
-    pub active: AtomicBool,
+/// Samples buffered between the reader thread and the main loop.
+/// Holds 128ms of reports from an 8kHz mouse.
+pub const SAMPLE_RING_CAPACITY: usize = 1024;
+
+/// One mouse report (everything up to a SYN_REPORT), stamped with the
+/// kernel's event time on CLOCK_MONOTONIC.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Sample {
+    pub time: Duration,
+    pub dx: i32,
+    pub dy: i32,
+}
+
+/// Button and wheel events buffered between the reader thread and the main
+/// loop.
+pub const EVENT_RING_CAPACITY: usize = 256;
+
+/// A button press or release, or wheel notches, stamped like `Sample`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ButtonEvent {
+    pub time: Duration,
+    pub input: Input,
+    /// Buttons: 1 pressed, 0 released. Wheel: movement in `WHEEL_NOTCH`
+    /// units per notch.
+    pub value: i32,
+}
+
+/// Current CLOCK_MONOTONIC time, comparable with `Sample::time`.
+pub fn monotonic_now() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// EVIOCSCLOCKID: _IOW('E', 0xa0, int)
+const EVIOCSCLOCKID: libc::c_ulong = 0x4004_45a0;
+
+/// Switch the device's event timestamps to CLOCK_MONOTONIC (they default to
+/// CLOCK_REALTIME, which can jump).
+fn set_monotonic_clock(device: &Device) -> std::io::Result<()> {
+    let clock: libc::c_int = libc::CLOCK_MONOTONIC;
+    let ret = unsafe { libc::ioctl(device.as_raw_fd(), EVIOCSCLOCKID, &clock) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Identity of an opened input device, used to look up per-device settings
+/// and to recognise the same mouse when it comes back after a disconnect.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: u16,
+    pub product: u16,
+    /// Physical path (port), e.g. "usb-0000:00:14.0-2/input0".
+    pub phys: String,
+    /// Serial number, empty if the device has none.
+    pub uniq: String,
+}
+
+impl DeviceInfo {
+    pub fn of(device: &Device) -> Self {
+        let id = device.input_id();
+        Self {
+            name: device.name().unwrap_or("unknown").to_string(),
+            vendor: id.vendor(),
+            product: id.product(),
+            phys: device.physical_path().unwrap_or("").to_string(),
+            uniq: device.unique_name().unwrap_or("").to_string(),
+        }
+    }
+
+    /// Same physical device. The node path and phys change with the port,
+    /// so only name, IDs and serial (when the device has one) are compared.
+    pub fn same_device(&self, other: &DeviceInfo) -> bool {
+        self.name == other.name
+            && self.vendor == other.vendor
+            && self.product == other.product
+            && self.uniq == other.uniq
+    }
+
+    /// Same interface of a composite device: the part of phys after the
+    /// port, e.g. "input0", which stays put when the port changes. A
+    /// receiver's mouse and keyboard share name, IDs and serial but not
+    /// this.
+    pub fn same_interface(&self, other: &DeviceInfo) -> bool {
+        let interface = |phys: &str| phys.rsplit_once('/').map(|(_, i)| i.to_string());
+        interface(&self.phys) == interface(&other.phys)
+    }
+}
+
+/// How often the reconnect wait re-checks the quit flag.
+const RECONNECT_POLL: Duration = Duration::from_millis(200);
+
+/// Longest a reader waits for events before re-checking the quit and active
+/// flags, i.e. the worst-case delay for grabbing or releasing the mouse.
+const READ_POLL: Duration = Duration::from_millis(50);
+
+/// Open an event node and switch it to kernel monotonic timestamps.
+/// The bool tells whether the clock switch worked.
+fn open_device(path: &std::path::Path) -> std::io::Result<(Device, bool)> {
+    let device = Device::open(path)?;
+    let kernel_clock = match set_monotonic_clock(&device) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!(
+                "Cannot use kernel event timestamps ({}); stamping on read",
+                e
+            );
+            false
+        }
+    };
+    Ok((device, kernel_clock))
+}
+
+/// Flags shared between a reader thread and the main loop.
+pub struct SourceState {
+    /// Input is forwarded and the device grabbed while set. Shared by every
+    /// device feeding the same pad, so one toggle switches them all.
+    pub active: Arc<AtomicBool>,
+    /// False while the device is unplugged and the reader waits for it.
+    pub connected: AtomicBool,
     pub quit: AtomicBool,
-    pub btn_left: AtomicBool,
-    pub btn_right: AtomicBool,
-    pub btns_dirty: AtomicBool,
 }
 
-impl MouseState {
-    pub fn new() -> Self {
+impl SourceState {
+    pub fn new(active: Arc<AtomicBool>) -> Self {
         Self {
-            dx: AtomicI32::new(0),
-            dy: AtomicI32::new(0),
-            active: AtomicBool::new(false),
+            active,
+            connected: AtomicBool::new(true),
             quit: AtomicBool::new(false),
-            btn_left: AtomicBool::new(false),
-            btn_right: AtomicBool::new(false),
-            btns_dirty: AtomicBool::new(false),
         }
     }
+}
+
+/// Whether a device reports relative motion and a left button.
+pub fn is_pointer(device: &Device) -> bool {
+    let rel = device.supported_relative_axes().is_some_and(|axes| {
+        axes.contains(RelativeAxisType::REL_X) && axes.contains(RelativeAxisType::REL_Y)
+    });
+    rel && device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(Key::BTN_LEFT))
+}
+
+/// Whether a device has letter keys and a space bar.
+pub fn is_keyboard(device: &Device) -> bool {
+    device.supported_keys().is_some_and(|keys| {
+        [Key::KEY_A, Key::KEY_Z, Key::KEY_SPACE]
+            .iter()
+            .all(|&k| keys.contains(k))
+    })
+}
 
-    /// Drain accumulated deltas, returning (dx, dy) and resetting to zero.
-    pub fn drain(&self) -> (i32, i32) {
-        let dx = self.dx.swap(0, Ordering::Relaxed);
-        let dy = self.dy.swap(0, Ordering::Relaxed);
-        (dx, dy)
+/// Whether a device is of the kind a filter looks for.
+pub fn is_kind(device: &Device, kind: DeviceKind) -> bool {
+    match kind {
+        DeviceKind::Mouse => is_pointer(device),
+        DeviceKind::Keyboard => is_keyboard(device),
+        DeviceKind::Gamepad => is_gamepad(device),
     }
 }
 
-/// Find a mouse device by enumerating /dev/input/event*.
-/// Returns the first device that supports REL_X, REL_Y, and BTN_LEFT.
-pub fn find_mouse_device() -> Option<PathBuf> {
-    use evdev::Key;
-    for i in 0..64 {
-        let path = PathBuf::from(format!("/dev/input/event{}", i));
-        if !path.exists() {
+fn has_hires_wheel(device: &Device) -> bool {
+    device
+        .supported_relative_axes()
+        .is_some_and(|axes| axes.contains(RelativeAxisType::REL_WHEEL_HI_RES))
+}
+
+/// Find a device of the filter's kind by enumerating /dev/input/event*.
+/// Returns the first device of the filter's kind it accepts that is not in
+/// `taken`.
+pub fn find_device(filter: &DeviceFilter, taken: &[PathBuf]) -> Option<PathBuf> {
+    for path in hotplug::event_nodes() {
+        if taken.contains(&path) {
             continue;
         }
-        if let Ok(device) = Device::open(&path) {
-            let has_rel_x = device
-                .supported_relative_axes()
-                .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X));
-            let has_rel_y = device
-                .supported_relative_axes()
-                .is_some_and(|axes| axes.contains(RelativeAxisType::REL_Y));
-            let has_btn_left = device
-                .supported_keys()
-                .is_some_and(|keys| keys.contains(Key::BTN_LEFT));
-
-            if has_rel_x && has_rel_y && has_btn_left {
+        let Ok(device) = Device::open(&path) else {
+            continue;
+        };
+        let info = DeviceInfo::of(&device);
+        match filter.check(&path, &info, is_kind(&device, filter.kind)) {
+            Verdict::Match => {
                 log::info!(
-                    "Found mouse: {} at {}",
-                    device.name().unwrap_or("unknown"),
+                    "Found {}: {} at {}",
+                    filter.kind.name(),
+                    info.name,
                     path.display()
                 );
                 return Some(path);
             }
+            verdict => log::debug!(
+                "Skipping {} ({}): {}",
+                path.display(),
+                info.name,
+                verdict.describe()
+            ),
         }
     }
     None
 }
 
-pub struct MouseReader {
+/// Reads one mouse, a keyboard (keys only) or a gamepad (buttons and
+/// axes) on its own thread.
+pub struct SourceReader {
     device: Device,
-    state: Arc<MouseState>,
+    kind: DeviceKind,
+    info: DeviceInfo,
+    state: Arc<SourceState>,
+    /// Mouse motion; not read when None, as for keyboards and gamepads.
+    samples: Option<Producer<Sample>>,
+    /// Button and wheel events; dropped when None, as for `probe`.
+    buttons: Option<Producer<ButtonEvent>>,
+    /// Gamepad axes, normalized with the device's ranges; not read when
+    /// None.
+    axes: Option<(Producer<PadAxes>, AxisRanges)>,
+    /// Axes of the report in progress, and whether it changed any.
+    pad_axes: PadAxes,
+    axes_dirty: bool,
+    /// Force feedback the virtual pad forwards to this device.
+    ff: Option<(FfReceiver, FfEffects)>,
+    /// Buttons currently down, released for the main loop on disconnect.
+    held: Vec<Key>,
+    /// Kernel timestamps are on CLOCK_MONOTONIC; otherwise stamp on read.
+    kernel_clock: bool,
+    /// The device reports REL_WHEEL_HI_RES.
+    hires_wheel: bool,
+    /// Motion of the report in progress (until the next SYN_REPORT).
+    pending: (i32, i32),
+    /// Grab the device while active; off for passive readers like `probe`.
+    grab: bool,
+    grabbed: bool,
 }
 
-impl MouseReader {
-    pub fn new(device_path: &str, state: Arc<MouseState>) -> std::io::Result<Self> {
-        let device = Device::open(device_path)?;
+impl SourceReader {
+    pub fn new(
+        device_path: &str,
+        state: Arc<SourceState>,
+        samples: Producer<Sample>,
+    ) -> std::io::Result<Self> {
+        Self::open(device_path, DeviceKind::Mouse, state, Some(samples))
+    }
+
+    /// A reader for a keyboard; it reports keys with `with_buttons`.
+    pub fn keyboard(device_path: &str, state: Arc<SourceState>) -> std::io::Result<Self> {
+        Self::open(device_path, DeviceKind::Keyboard, state, None)
+    }
+
+    /// A reader for a gamepad; it reports buttons with `with_buttons` and
+    /// axes with `with_axes`.
+    pub fn gamepad(device_path: &str, state: Arc<SourceState>) -> std::io::Result<Self> {
+        Self::open(device_path, DeviceKind::Gamepad, state, None)
+    }
+
+    fn open(
+        device_path: &str,
+        kind: DeviceKind,
+        state: Arc<SourceState>,
+        samples: Option<Producer<Sample>>,
+    ) -> std::io::Result<Self> {
+        let (device, kernel_clock) = open_device(device_path.as_ref())?;
         log::info!(
-            "Opened mouse device: {} ({})",
+            "Opened {} device: {} ({})",
+            kind.name(),
             device.name().unwrap_or("unknown"),
             device_path
         );
-        Ok(Self { device, state })
+        Ok(Self {
+            kind,
+            info: DeviceInfo::of(&device),
+            hires_wheel: has_hires_wheel(&device),
+            device,
+            state,
+            samples,
+            buttons: None,
+            axes: None,
+            pad_axes: PadAxes::default(),
+            axes_dirty: false,
+            ff: None,
+            held: Vec::new(),
+            kernel_clock,
+            pending: (0, 0),
+            grab: true,
+            grabbed: false,
+        })
+    }
+
+    /// Read without ever grabbing the device.
+    pub fn passive(mut self) -> Self {
+        self.grab = false;
+        self
+    }
+
+    /// Also report buttons and the wheel.
+    pub fn with_buttons(mut self, buttons: Producer<ButtonEvent>) -> Self {
+        self.buttons = Some(buttons);
+        self
+    }
+
+    /// Also report gamepad axes, after every report that moves one.
+    pub fn with_axes(mut self, axes: Producer<PadAxes>) -> Self {
+        self.axes = Some((axes, AxisRanges::of(&self.device)));
+        self
+    }
+
+    /// Play the virtual pad's force feedback on this device.
+    pub fn with_ff(mut self, ff: FfReceiver) -> Self {
+        self.ff = Some((ff, FfEffects::default()));
+        self
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// Force feedback effects the device plays, if any.
+    pub fn force_feedback(&self) -> Option<AttributeSet<FFEffectType>> {
+        let ff = self.device.supported_ff()?;
+        let mut types = AttributeSet::new();
+        for t in ff.iter() {
+            types.insert(t);
+        }
+        Some(types)
     }
 
     /// Run the blocking event loop. Call from a dedicated thread.
@@ -90,39 +349,40 @@ impl MouseReader {
                 break;
             }
 
-            // Check for external toggle signal (SIGUSR1 via `m2joy toggle`)
-            if crate::TOGGLE
-                .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
-                .is_ok()
-            {
-                let was_active = self.state.active.load(Ordering::Relaxed);
-                if was_active {
-                    self.state.active.store(false, Ordering::Relaxed);
-                    if let Err(e) = self.device.ungrab() {
-                        log::warn!("Failed to ungrab mouse: {}", e);
-                    }
-                    log::info!("Mouse released");
-                } else {
-                    if let Err(e) = self.device.grab() {
-                        log::warn!("Failed to grab mouse: {}", e);
-                    }
-                    self.state.active.store(true, Ordering::Relaxed);
-                    log::info!("Mouse grabbed");
+            self.sync_grab();
+
+            match self.wait_readable() {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    log::error!("Error waiting for {} events: {}", self.kind.name(), e);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    continue;
                 }
             }
 
-            let events: Vec<_> = match self.device.fetch_events() {
-                Ok(iter) => iter.collect(),
+            let fetched = self
+                .device
+                .fetch_events()
+                .map(|iter| iter.collect::<Vec<_>>());
+            let events = match fetched {
+                Ok(events) => events,
                 Err(e) => {
                     if self.state.quit.load(Ordering::Relaxed) {
                         break;
                     }
-                    // SIGUSR1 interrupts the blocking read with EINTR — just loop
-                    // back and check the TOGGLE flag above.
+                    // A signal interrupts the read with EINTR — just loop back
+                    // and check the flags above.
                     if e.kind() == std::io::ErrorKind::Interrupted {
                         continue;
                     }
-                    log::error!("Error reading mouse events: {}", e);
+                    if e.raw_os_error() == Some(libc::ENODEV) {
+                        if self.reconnect() {
+                            continue;
+                        }
+                        break;
+                    }
+                    log::error!("Error reading {} events: {}", self.kind.name(), e);
                     std::thread::sleep(std::time::Duration::from_millis(10));
                     continue;
                 }
@@ -130,38 +390,271 @@ impl MouseReader {
 
             for ev in &events {
                 if !self.state.active.load(Ordering::Relaxed) {
+                    self.pending = (0, 0);
+                    self.axes_dirty = false;
+                    self.held.clear();
                     continue;
                 }
                 match ev.kind() {
                     InputEventKind::RelAxis(axis) => match axis {
-                        RelativeAxisType::REL_X => {
-                            self.state.dx.fetch_add(ev.value(), Ordering::Relaxed);
+                        RelativeAxisType::REL_X => self.pending.0 += ev.value(),
+                        RelativeAxisType::REL_Y => self.pending.1 += ev.value(),
+                        // Hi-res wheels also send the notch events; use
+                        // one or the other, in hi-res units either way.
+                        RelativeAxisType::REL_WHEEL_HI_RES if self.hires_wheel => {
+                            self.push_button(ev, Input::Wheel, ev.value())
+                        }
+                        RelativeAxisType::REL_HWHEEL_HI_RES if self.hires_wheel => {
+                            self.push_button(ev, Input::HWheel, ev.value())
+                        }
+                        RelativeAxisType::REL_WHEEL if !self.hires_wheel => {
+                            self.push_button(ev, Input::Wheel, ev.value() * WHEEL_NOTCH)
                         }
-                        RelativeAxisType::REL_Y => {
-                            self.state.dy.fetch_add(ev.value(), Ordering::Relaxed);
+                        RelativeAxisType::REL_HWHEEL if !self.hires_wheel => {
+                            self.push_button(ev, Input::HWheel, ev.value() * WHEEL_NOTCH)
                         }
                         _ => {}
                     },
-                    InputEventKind::Key(key) => {
-                        let pressed = ev.value() != 0;
-                        match key {
-                            Key::BTN_LEFT => {
-                                self.state.btn_left.store(pressed, Ordering::Relaxed);
-                                self.state.btns_dirty.store(true, Ordering::Relaxed);
-                            }
-                            Key::BTN_RIGHT => {
-                                self.state.btn_right.store(pressed, Ordering::Relaxed);
-                                self.state.btns_dirty.store(true, Ordering::Relaxed);
+                    InputEventKind::AbsAxis(axis) => {
+                        if let Some((_, ranges)) = &self.axes {
+                            self.axes_dirty |= ranges.set(&mut self.pad_axes, axis, ev.value());
+                        }
+                    }
+                    InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                        if self.pending != (0, 0) {
+                            let time = self.event_time(ev);
+                            let (dx, dy) = std::mem::take(&mut self.pending);
+                            if let Some(samples) = &mut self.samples {
+                                if !samples.push(Sample { time, dx, dy }) {
+                                    log::warn!("Sample buffer full, dropping mouse report");
+                                }
                             }
-                            _ => {}
+                        }
+                        if std::mem::take(&mut self.axes_dirty) {
+                            self.push_axes(self.pad_axes);
                         }
                     }
+                    // Autorepeat (2) carries nothing new.
+                    InputEventKind::Key(key) if ev.value() != 2 => {
+                        let pressed = ev.value() != 0;
+                        if pressed {
+                            self.held.push(key);
+                        } else if let Some(i) = self.held.iter().position(|&k| k == key) {
+                            self.held.swap_remove(i);
+                        }
+                        self.push_button(ev, Input::Key(key), pressed as i32);
+                    }
                     _ => {}
                 }
             }
         }
 
         // Ungrab on exit
-        let _ = self.device.ungrab();
+        if self.grabbed {
+            let _ = self.device.ungrab();
+        }
+    }
+
+    fn event_time(&self, ev: &evdev::InputEvent) -> Duration {
+        if self.kernel_clock {
+            ev.timestamp()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+        } else {
+            monotonic_now()
+        }
+    }
+
+    fn push_button(&mut self, ev: &evdev::InputEvent, input: Input, value: i32) {
+        let time = self.event_time(ev);
+        if let Some(buttons) = &mut self.buttons {
+            if !buttons.push(ButtonEvent { time, input, value }) {
+                log::warn!("Button buffer full, dropping {} event", input);
+            }
+        }
+    }
+
+    fn push_axes(&mut self, axes: PadAxes) {
+        if let Some((producer, _)) = &mut self.axes {
+            if !producer.push(axes) {
+                log::warn!("Axis buffer full, dropping gamepad report");
+            }
+        }
+    }
+
+    /// Grab or release the device to follow the shared active flag.
+    fn sync_grab(&mut self) {
+        let want = self.grab && self.state.active.load(Ordering::Relaxed);
+        if want == self.grabbed {
+            return;
+        }
+        let result = if want {
+            self.device.grab()
+        } else {
+            self.device.ungrab()
+        };
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to {} '{}': {}",
+                if want { "grab" } else { "ungrab" },
+                self.info.name,
+                e
+            );
+        }
+        self.grabbed = want;
+        // Axes that moved while released were not reported; start from
+        // where they are now.
+        if want {
+            if let Some((_, ranges)) = &self.axes {
+                self.pad_axes = ranges.current(&self.device);
+                self.push_axes(self.pad_axes);
+            }
+        }
+    }
+
+    /// Wait up to `READ_POLL` for the device to have events (or an error,
+    /// which the following read reports). EINTR counts as a timeout. Force
+    /// feedback requests arriving meanwhile are played right away.
+    fn wait_readable(&mut self) -> std::io::Result<bool> {
+        let pollfd = |fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let mut fds = [
+            pollfd(self.device.as_raw_fd()),
+            pollfd(self.ff.as_ref().map_or(-1, |(rx, _)| rx.fd())),
+        ];
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), 2, READ_POLL.as_millis() as libc::c_int) };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(e);
+        }
+        if fds[1].revents != 0 {
+            self.play_ff();
+        }
+        Ok(fds[0].revents != 0)
+    }
+
+    fn play_ff(&mut self) {
+        let Some((rx, effects)) = &mut self.ff else {
+            return;
+        };
+        for command in rx.drain() {
+            if let Err(e) = effects.apply(&mut self.device, command) {
+                log::warn!("Force feedback to '{}' failed: {}", self.info.name, e);
+            }
+        }
+    }
+
+    /// The device is gone (ENODEV). Release everything it was holding, then
+    /// watch /dev/input until the same device shows up again and reopen it.
+    /// Returns false if asked to quit first.
+    fn reconnect(&mut self) -> bool {
+        log::warn!(
+            "Lost {} '{}'; waiting for it to return",
+            self.kind.name(),
+            self.info.name
+        );
+        self.state.connected.store(false, Ordering::Relaxed);
+        self.pending = (0, 0);
+        if self.axes.is_some() {
+            self.pad_axes = PadAxes::default();
+            self.axes_dirty = false;
+            self.push_axes(self.pad_axes);
+        }
+        let time = monotonic_now();
+        for key in self.held.drain(..) {
+            if let Some(buttons) = &mut self.buttons {
+                buttons.push(ButtonEvent {
+                    time,
+                    input: Input::Key(key),
+                    value: 0,
+                });
+            }
+        }
+
+        // Watch before scanning so a node created in between is not missed.
+        let watcher = match DeviceWatcher::new() {
+            Ok(w) => Some(w),
+            Err(e) => {
+                log::warn!("Cannot watch /dev/input ({}); polling instead", e);
+                None
+            }
+        };
+        let mut candidates = hotplug::event_nodes();
+
+        loop {
+            for path in candidates.drain(..) {
+                let Ok((device, kernel_clock)) = open_device(&path) else {
+                    continue;
+                };
+                let info = DeviceInfo::of(&device);
+                if !is_kind(&device, self.kind)
+                    || !info.same_device(&self.info)
+                    || !info.same_interface(&self.info)
+                {
+                    continue;
+                }
+                self.info = info;
+                self.hires_wheel = has_hires_wheel(&device);
+                if let Some((_, ranges)) = &mut self.axes {
+                    *ranges = AxisRanges::of(&device);
+                }
+                self.device = device;
+                if let Some((_, effects)) = &mut self.ff {
+                    effects.reupload(&mut self.device);
+                }
+                self.kernel_clock = kernel_clock;
+                self.grabbed = false;
+                self.sync_grab();
+                self.state.connected.store(true, Ordering::Relaxed);
+                log::info!("Reopened {} at {}", self.kind.name(), path.display());
+                return true;
+            }
+
+            if self.state.quit.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            candidates = match &watcher {
+                Some(w) => w.wait(RECONNECT_POLL).unwrap_or_else(|e| {
+                    log::warn!("Error watching /dev/input: {}", e);
+                    std::thread::sleep(RECONNECT_POLL);
+                    hotplug::event_nodes()
+                }),
+                None => {
+                    std::thread::sleep(RECONNECT_POLL);
+                    hotplug::event_nodes()
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(phys: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: "Logitech USB Receiver".to_string(),
+            vendor: 0x046d,
+            product: 0xc52b,
+            phys: phys.to_string(),
+            uniq: String::new(),
+        }
+    }
+
+    #[test]
+    fn interface_survives_a_port_change() {
+        let mouse = info("usb-0000:00:14.0-2/input0");
+        assert!(mouse.same_interface(&info("usb-0000:00:14.0-4/input0")));
+        assert!(!mouse.same_interface(&info("usb-0000:00:14.0-2/input1")));
+        assert!(info("").same_interface(&info("")));
+        assert!(!info("").same_interface(&mouse));
     }
 }
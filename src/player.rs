@@ -0,0 +1,470 @@
+use crate::ff::{FfCommand, FfReporter};
+use crate::gamepad::Merge;
+use crate::keyboard::Movement;
+use crate::mapping::{Action, Mapper};
+use crate::motion::MotionSensor;
+use crate::mouse::{monotonic_now, ButtonEvent};
+use crate::profile::{PlayerSettings, Profile, Stick};
+use crate::source::{GamepadSource, KeyboardSource, MouseSource};
+use crate::virtual_pad::{PadState, VirtualPad, STICK_MAX};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Most players one daemon runs; toggles address them with a bit each.
+pub const MAX_PLAYERS: usize = 16;
+
+/// Factor per `sensitivity-up` / `sensitivity-down`.
+const SENSITIVITY_STEP: f32 = 1.1;
+
+/// One player: a profile, the mice feeding it and its virtual pad, with its
+/// own grab state.
+pub struct Player {
+    /// 1-based, as in `m2joy toggle --player N`.
+    pub number: usize,
+    profile_name: Option<String>,
+    pub profile: Profile,
+    /// Profiles `next-profile` cycles through.
+    profiles: Vec<(String, Profile)>,
+    pad: VirtualPad,
+    pub sources: Vec<MouseSource>,
+    pub keyboard: Option<KeyboardSource>,
+    /// The keyboard's movement keys as a stick.
+    movement: Movement,
+    pub gamepad: Option<GamepadSource>,
+    /// Force feedback requests from games this tick.
+    ff: Vec<FfCommand>,
+    /// Logs or hooks them instead of the physical pad playing them.
+    ff_reporter: Option<FfReporter>,
+    /// The mice as a gyro.
+    motion: Option<MotionSensor>,
+    /// Grab state shared with this player's readers.
+    active: Arc<AtomicBool>,
+    /// Last emitted position per stick, indexed by `Stick as usize`.
+    prev: [(i32, i32); 2],
+    was_active: bool,
+    mapper: Mapper,
+    /// Button and wheel events of every mouse this tick.
+    events: Vec<ButtonEvent>,
+    /// Actions those events triggered.
+    actions: Vec<Action>,
+    /// Grab state, profile or layer changed since the status file was
+    /// last written.
+    status_changed: bool,
+    /// Prefix for log and debug lines ("P2: "); empty with a single player.
+    label: String,
+    dbg_start: Instant,
+    dbg_ticks: u32,
+}
+
+impl Player {
+    pub fn new(
+        number: usize,
+        settings: &PlayerSettings,
+        pad: VirtualPad,
+        sources: Vec<MouseSource>,
+        active: Arc<AtomicBool>,
+        label: String,
+    ) -> Self {
+        Self {
+            number,
+            profile_name: settings.profile_name.clone(),
+            profile: settings.profile.clone(),
+            profiles: Vec::new(),
+            mapper: Mapper::new(&settings.profile),
+            events: Vec::new(),
+            actions: Vec::new(),
+            pad,
+            sources,
+            keyboard: None,
+            movement: Movement::new(settings.profile.keyboard.as_ref()),
+            gamepad: None,
+            ff: Vec::new(),
+            ff_reporter: None,
+            motion: None,
+            active,
+            prev: [(0, 0); 2],
+            was_active: false,
+            status_changed: true,
+            label,
+            dbg_start: Instant::now(),
+            dbg_ticks: 0,
+        }
+    }
+
+    /// Profiles to cycle through with `next-profile`.
+    pub fn with_profiles(mut self, profiles: Vec<(String, Profile)>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// A keyboard grabbed along with the mice.
+    pub fn with_keyboard(mut self, keyboard: KeyboardSource) -> Self {
+        self.keyboard = Some(keyboard);
+        self
+    }
+
+    /// A physical gamepad merged into the virtual one, grabbed along with
+    /// the mice.
+    pub fn with_gamepad(mut self, gamepad: GamepadSource) -> Self {
+        self.gamepad = Some(gamepad);
+        self
+    }
+
+    /// Send the pad's force feedback to a log or hook command.
+    pub fn with_ff_reporter(mut self, reporter: FfReporter) -> Self {
+        self.ff_reporter = Some(reporter);
+        self
+    }
+
+    /// Turn mouse motion into gyro rates on a motion sensor device.
+    pub fn with_motion(mut self, motion: MotionSensor) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+
+    /// Grab or release this player's mice (and keyboard and gamepad).
+    pub fn toggle(&mut self) {
+        let grab = !self.active.load(Ordering::Relaxed);
+        self.active.store(grab, Ordering::Relaxed);
+        self.status_changed = true;
+        log::info!(
+            "{}{}",
+            self.label,
+            if grab {
+                "Mouse grabbed"
+            } else {
+                "Mouse released"
+            }
+        );
+    }
+
+    /// One line for `m2joy status`.
+    pub fn status(&self) -> String {
+        format!(
+            "player {}: {}, profile {}, layer {}, sensitivity {:.2}",
+            self.number,
+            if self.active.load(Ordering::Relaxed) {
+                "grabbed"
+            } else {
+                "released"
+            },
+            self.profile_name.as_deref().unwrap_or("-"),
+            self.mapper.layer().unwrap_or("-"),
+            self.profile.sensitivity,
+        )
+    }
+
+    /// Whether `status()` changed since the last call.
+    pub fn take_status_change(&mut self) -> bool {
+        std::mem::take(&mut self.status_changed)
+    }
+
+    /// A reader asked to stop, or died on its own.
+    pub fn quit_requested(&self) -> bool {
+        self.sources
+            .iter()
+            .map(|s| &s.state)
+            .chain(self.keyboard.iter().map(|k| &k.state))
+            .chain(self.gamepad.iter().map(|g| &g.state))
+            .any(|state| state.quit.load(Ordering::Relaxed))
+    }
+
+    pub fn reader_died(&self) -> bool {
+        self.sources.iter().any(MouseSource::is_finished)
+            || self
+                .keyboard
+                .as_ref()
+                .is_some_and(KeyboardSource::is_finished)
+            || self
+                .gamepad
+                .as_ref()
+                .is_some_and(GamepadSource::is_finished)
+    }
+
+    /// One main loop iteration: `dt` seconds since the last one.
+    pub fn tick(&mut self, dt: f32, now: Instant, debug: bool) {
+        // Sum every mouse routed to a stick. A mouse that is unplugged
+        // contributes nothing, as if released.
+        // An active layer can move every mouse to another stick.
+        let is_active = self.active.load(Ordering::Relaxed);
+        let layer_stick = self.mapper.stick();
+        let mut deflection = [(0.0f32, 0.0f32); 2];
+        // With motion sensors, every mouse turns the gyro instead
+        let to_stick = self.motion.as_ref().is_none_or(MotionSensor::stick);
+        let mut counts = (0, 0);
+        for source in &mut self.sources {
+            source.drain();
+            self.events.extend_from_slice(&source.events);
+            if is_active && source.connected() {
+                let (x, y) = source.update(dt, &self.profile, debug);
+                if to_stick {
+                    let d = &mut deflection[layer_stick.unwrap_or(source.stick) as usize];
+                    d.0 += x;
+                    d.1 += y;
+                }
+                let (cx, cy) = source.counts();
+                counts.0 += cx;
+                counts.1 += cy;
+            } else {
+                source.reset();
+            }
+        }
+        // Movement keys drive their stick; every other key is mapped like
+        // a mouse button.
+        if let Some(keyboard) = &mut self.keyboard {
+            keyboard.drain();
+            for event in &keyboard.events {
+                if !self.movement.feed(event) {
+                    self.events.push(*event);
+                }
+            }
+        }
+
+        // Games' force feedback goes to its sink, grabbed or not
+        self.pad.ff_requests(&mut self.ff);
+        for command in self.ff.drain(..) {
+            if let Some(reporter) = &self.ff_reporter {
+                reporter.send(command);
+            } else if let Some(gamepad) = &self.gamepad {
+                gamepad.forward_ff(command);
+            }
+        }
+        if let Some(gamepad) = &mut self.gamepad {
+            gamepad.drain();
+        }
+
+        if !is_active {
+            // Not active — center sticks and let go of the buttons
+            self.events.clear();
+            if self.was_active {
+                self.was_active = false;
+                self.mapper.reset();
+                self.movement.reset();
+                if let Some(gamepad) = &mut self.gamepad {
+                    gamepad.reset();
+                }
+                self.mapper.take_layer_change();
+                self.release_buttons();
+            }
+            if let Some(motion) = &mut self.motion {
+                if let Err(e) = motion.rest() {
+                    log::warn!("Failed to emit motion: {}", e);
+                }
+            }
+            for stick in Stick::ALL {
+                if self.prev[stick as usize] != (0, 0) {
+                    self.prev[stick as usize] = (0, 0);
+                    let _ = self.pad.emit_stick(stick, 0, 0);
+                }
+            }
+            return;
+        }
+        self.was_active = true;
+
+        // Buttons and wheel through the profile's bindings, in the order
+        // they happened across all mice.
+        self.events.sort_by_key(|e| e.time);
+        for event in &self.events {
+            self.mapper.feed(event, &mut self.actions);
+        }
+        self.events.clear();
+        let clock = monotonic_now();
+        self.mapper.poll(clock, &mut self.actions);
+        for action in std::mem::take(&mut self.actions) {
+            self.run(action);
+        }
+        if self.mapper.take_layer_change() {
+            self.status_changed = true;
+            if debug {
+                eprintln!(
+                    "[dbg] {}layer={}",
+                    self.label,
+                    self.mapper.layer().unwrap_or("-")
+                );
+            }
+        }
+        if let Some(motion) = &mut self.motion {
+            if let Err(e) = motion.update(counts, clock) {
+                log::warn!("Failed to emit motion: {}", e);
+            }
+        }
+        let mut mapped = self.mapper.state(clock);
+        let (mx, my) = self.movement.update(dt);
+        let keys = &mut mapped.sticks[self.movement.stick() as usize];
+        keys.0 += mx;
+        keys.1 += my;
+        // The physical pad's buttons and triggers on top of the mapped ones
+        if let Some(gamepad) = &self.gamepad {
+            let physical = gamepad.physical.pad();
+            mapped.pad.buttons |= physical.buttons;
+            for (t, p) in mapped.pad.triggers.iter_mut().zip(physical.triggers) {
+                *t = t.max(p);
+            }
+        }
+        let merge = self
+            .profile
+            .gamepad
+            .as_ref()
+            .map_or_else(Merge::default, |g| g.merge);
+
+        for stick in Stick::ALL {
+            let (dx, dy) = deflection[stick as usize];
+            let (cx, cy) = self.profile.curve.shape(dx, dy);
+            // Stick outputs of held buttons and movement keys go on top of
+            // the shaped motion
+            let (ox, oy) = mapped.sticks[stick as usize];
+            let (x, y) = self.pad.shape(cx + ox, cy + oy);
+            // The physical pad's stick is already shaped by its hardware
+            let (x, y) = match &self.gamepad {
+                Some(gamepad) => merge.apply((x, y), gamepad.physical.stick(stick)),
+                None => (x, y),
+            };
+            let pos = (
+                (x * STICK_MAX as f32).round() as i32,
+                (y * STICK_MAX as f32).round() as i32,
+            );
+
+            // Only emit when values actually change
+            if pos != self.prev[stick as usize] {
+                if let Err(e) = self.pad.emit_stick_raw(stick, pos.0, pos.1) {
+                    log::warn!("Failed to emit stick: {}", e);
+                }
+                self.prev[stick as usize] = pos;
+            }
+        }
+
+        if let Err(e) = self.pad.update(&mapped.pad) {
+            log::warn!("Failed to emit buttons: {}", e);
+        }
+
+        // Debug: print every 100ms
+        if debug {
+            self.print_debug(now);
+        }
+    }
+
+    fn print_debug(&mut self, now: Instant) {
+        self.dbg_ticks += 1;
+        let span = now.duration_since(self.dbg_start);
+        if span < Duration::from_millis(100) {
+            return;
+        }
+        let multi = self.sources.len() > 1;
+        let layer_stick = self.mapper.stick();
+        let layer = self.mapper.layer().unwrap_or("-");
+        for s in &mut self.sources {
+            let (vel_x, vel_y) = s.vel;
+            if s.dbg_raw != (0, 0) || vel_x != 0.0 || vel_y != 0.0 {
+                let (sx, sy) = self.prev[layer_stick.unwrap_or(s.stick) as usize];
+                let mut prefix = self.label.clone();
+                if multi {
+                    prefix.push_str(&s.name);
+                    prefix.push(':');
+                }
+                let prefix = prefix.trim_end();
+                eprintln!(
+                    "[dbg]{}{} raw({:+5},{:+5}) n={:<3} vel({:+7.0},{:+7.0}) out({:+6},{:+6}) loop={:.0}Hz mouse={:.0}Hz layer={}",
+                    if prefix.is_empty() { "" } else { " " },
+                    prefix,
+                    s.dbg_raw.0,
+                    s.dbg_raw.1,
+                    s.dbg_samples,
+                    vel_x,
+                    vel_y,
+                    sx.clamp(-32767, 32767),
+                    sy.clamp(-32767, 32767),
+                    self.dbg_ticks as f32 / span.as_secs_f32(),
+                    s.report_rate(),
+                    layer,
+                );
+            }
+            s.dbg_raw = (0, 0);
+            s.dbg_samples = 0;
+        }
+        self.dbg_start = now;
+        self.dbg_ticks = 0;
+    }
+
+    fn run(&mut self, action: Action) {
+        match action {
+            Action::ReleaseGrab => {
+                if self.active.load(Ordering::Relaxed) {
+                    self.toggle();
+                }
+            }
+            Action::NextProfile => self.next_profile(),
+            Action::SensitivityUp | Action::SensitivityDown => {
+                let factor = if action == Action::SensitivityUp {
+                    SENSITIVITY_STEP
+                } else {
+                    1.0 / SENSITIVITY_STEP
+                };
+                self.profile.sensitivity *= factor;
+                for source in &mut self.sources {
+                    source.scale_sensitivity(factor);
+                }
+                self.status_changed = true;
+                log::info!("{}Sensitivity {:.2}", self.label, self.profile.sensitivity);
+            }
+        }
+    }
+
+    /// Switch to the profile after the current one by name. The mice stay
+    /// open; each takes the tuning of the new profile's entry at its
+    /// position (or the last one).
+    fn next_profile(&mut self) {
+        if self.profiles.is_empty() {
+            log::warn!("{}No profiles to switch to", self.label);
+            return;
+        }
+        let current = self
+            .profile_name
+            .as_ref()
+            .and_then(|name| self.profiles.iter().position(|(n, _)| n == name));
+        let next = current.map_or(0, |i| (i + 1) % self.profiles.len());
+        let (name, profile) = self.profiles[next].clone();
+
+        let specs = profile.mice();
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            source.retune(&specs[i.min(specs.len() - 1)], &profile);
+        }
+        self.pad.set_shaping(profile.deadzone, profile.gate);
+        self.mapper.set_profile(&profile);
+        self.movement = Movement::new(profile.keyboard.as_ref());
+        log::info!("{}Profile: {}", self.label, name);
+        self.profile = profile;
+        self.profile_name = Some(name);
+        self.status_changed = true;
+    }
+
+    fn release_buttons(&mut self) {
+        if let Err(e) = self.pad.update(&PadState::default()) {
+            log::warn!("Failed to emit buttons: {}", e);
+        }
+    }
+
+    /// Center the sticks and let go of every button, e.g. before exiting.
+    pub fn neutral(&mut self) {
+        for stick in Stick::ALL {
+            let _ = self.pad.emit_stick(stick, 0, 0);
+        }
+        if let Some(motion) = &mut self.motion {
+            let _ = motion.rest();
+        }
+        self.release_buttons();
+    }
+
+    /// Stop the reader threads and wait for them to release their devices.
+    pub fn stop(self) {
+        for source in self.sources {
+            source.stop();
+        }
+        if let Some(keyboard) = self.keyboard {
+            keyboard.stop();
+        }
+        if let Some(gamepad) = self.gamepad {
+            gamepad.stop();
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::mouse::{Sample, SourceReader, SourceState, SAMPLE_RING_CAPACITY};
+use crate::ring;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The built-in smoothing defaults were tuned on a 125Hz mouse moving
+/// ~4 counts per report.
+const REF_REPORT_RATE: f32 = 125.0;
+const REF_COUNTS_PER_REPORT: f32 = 4.0;
+
+/// Smallest factor time constants are scaled by. Below this the filters
+/// stop hiding count quantization, whatever the report rate.
+const MIN_FACTOR: f32 = 0.25;
+const MAX_IDLE_FACTOR: f32 = 2.0;
+
+/// Reports of continuous motion needed before an estimate is trusted.
+const PROBE_REPORTS: usize = 250;
+
+/// Gaps longer than this are pauses in motion, not report intervals.
+const MAX_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long `m2joy probe` waits for enough motion.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Measured (or configured) report behaviour of a mouse.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct MouseCharacteristics {
+    /// Reports per second during continuous motion, in Hz.
+    pub report_rate: f32,
+    /// Typical motion per report, in counts.
+    pub counts_per_report: f32,
+}
+
+impl MouseCharacteristics {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.report_rate <= 0.0 || self.counts_per_report <= 0.0 {
+            return Err("mouse.report_rate and mouse.counts_per_report must be > 0".into());
+        }
+        Ok(())
+    }
+
+    /// Factor for smoothing time constants, relative to the 125Hz reference.
+    /// Faster mice have less gap between reports to bridge.
+    pub fn smoothing_factor(&self) -> f32 {
+        (REF_REPORT_RATE / self.report_rate).clamp(MIN_FACTOR, 1.0)
+    }
+
+    /// Factor for the idle timeout. A mouse that produces counts faster
+    /// (higher rate or CPI) shows a stop sooner.
+    pub fn idle_factor(&self) -> f32 {
+        let reference = REF_REPORT_RATE * REF_COUNTS_PER_REPORT;
+        (reference / (self.report_rate * self.counts_per_report)).clamp(MIN_FACTOR, MAX_IDLE_FACTOR)
+    }
+}
+
+/// Collects report intervals and sizes until an estimate is possible.
+#[derive(Default)]
+pub struct ProbeStats {
+    last: Option<Duration>,
+    intervals: Vec<f32>,
+    sizes: Vec<f32>,
+}
+
+impl ProbeStats {
+    pub fn add(&mut self, s: &Sample) {
+        if let Some(prev) = self.last {
+            let gap = s.time.saturating_sub(prev);
+            if !gap.is_zero() && gap <= MAX_INTERVAL {
+                self.intervals.push(gap.as_secs_f32());
+                self.sizes.push((s.dx as f32).hypot(s.dy as f32));
+            }
+        }
+        self.last = Some(s.time);
+    }
+
+    pub fn progress(&self) -> f32 {
+        (self.intervals.len() as f32 / PROBE_REPORTS as f32).min(1.0)
+    }
+
+    /// Estimate once enough continuous motion has been seen.
+    pub fn estimate(&self) -> Option<MouseCharacteristics> {
+        if self.intervals.len() < PROBE_REPORTS {
+            return None;
+        }
+        // Slow motion skips polls that had no counts, so the lower quartile
+        // of the intervals is the polling period; the median size is typical.
+        let period = percentile(&self.intervals, 0.25);
+        Some(MouseCharacteristics {
+            report_rate: 1.0 / period,
+            counts_per_report: percentile(&self.sizes, 0.5).max(1.0),
+        })
+    }
+}
+
+fn percentile(values: &[f32], p: f32) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    sorted[((sorted.len() - 1) as f32 * p).round() as usize]
+}
+
+/// `m2joy probe`: read the mouse (without grabbing it) while the user moves
+/// it, then print the estimated report rate and counts per report.
+pub fn run(device_path: &str) {
+    let state = Arc::new(SourceState::new(Arc::new(AtomicBool::new(true))));
+    let (tx, mut rx) = ring::channel(SAMPLE_RING_CAPACITY);
+    let mut reader = match SourceReader::new(device_path, Arc::clone(&state), tx) {
+        Ok(r) => r.passive(),
+        Err(e) => {
+            log::error!("Failed to open mouse device: {}", e);
+            log::error!("Check permissions on {}", device_path);
+            std::process::exit(1);
+        }
+    };
+    let reader_thread = std::thread::Builder::new()
+        .name("mouse-reader".into())
+        .spawn(move || reader.run())
+        .expect("Failed to spawn mouse thread");
+
+    println!("Move the mouse in steady circles until the bar fills...");
+    let mut stats = ProbeStats::default();
+    let start = Instant::now();
+    let estimate = loop {
+        for s in rx.drain() {
+            stats.add(&s);
+        }
+        if let Some(e) = stats.estimate() {
+            break Some(e);
+        }
+        if start.elapsed() > PROBE_TIMEOUT || crate::QUIT.load(Ordering::Relaxed) {
+            break None;
+        }
+        let filled = (stats.progress() * 30.0) as usize;
+        eprint!("\r[{:<30}]", "#".repeat(filled));
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    eprintln!();
+
+    state.quit.store(true, Ordering::Relaxed);
+    let _ = reader_thread.join();
+
+    let Some(m) = estimate else {
+        eprintln!("Not enough mouse motion detected; try again and keep moving.");
+        std::process::exit(1);
+    };
+    println!("Report rate:       ~{:.0} Hz", m.report_rate);
+    println!("Counts per report: ~{:.1}", m.counts_per_report);
+    println!("Smoothing scale:   x{:.2}", m.smoothing_factor());
+    println!("Idle timeout:      x{:.2}", m.idle_factor());
+    println!();
+    println!("To skip auto-detection, add to your profile:");
+    println!(
+        "  mouse = {{ report_rate = {:.0}, counts_per_report = {:.1} }}",
+        m.report_rate, m.counts_per_report
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(us: u64, dx: i32, dy: i32) -> Sample {
+        Sample {
+            time: Duration::from_micros(us),
+            dx,
+            dy,
+        }
+    }
+
+    #[test]
+    fn estimates_a_1000hz_mouse_that_skips_polls() {
+        let mut stats = ProbeStats::default();
+        let mut t = 0;
+        for i in 0..300 {
+            // One poll in four had no counts, doubling its interval
+            t += if i % 4 == 3 { 2000 } else { 1000 };
+            // Every 100 reports, a pause that is not an interval
+            if i % 100 == 0 {
+                t += 500_000;
+            }
+            let size = [3, 4, 5][i % 3];
+            stats.add(&sample(t, size, 0));
+        }
+        let estimate = stats.estimate().unwrap();
+        assert!((estimate.report_rate - 1000.0).abs() < 1.0);
+        assert_eq!(estimate.counts_per_report, 4.0);
+    }
+
+    #[test]
+    fn needs_enough_reports() {
+        let mut stats = ProbeStats::default();
+        for i in 0..=PROBE_REPORTS as u64 / 2 {
+            stats.add(&sample(i * 8000, 0, 2));
+        }
+        assert_eq!(stats.progress(), 0.5);
+        assert!(stats.estimate().is_none());
+        for i in 0..PROBE_REPORTS as u64 {
+            stats.add(&sample(1_000_000 + i * 8000, 0, 2));
+        }
+        let estimate = stats.estimate().unwrap();
+        assert!((estimate.report_rate - 125.0).abs() < 0.1);
+        // Sizes are the length of the motion
+        assert_eq!(estimate.counts_per_report, 2.0);
+    }
+
+    #[test]
+    fn factors_are_relative_to_a_125hz_mouse() {
+        let mouse = |report_rate, counts_per_report| MouseCharacteristics {
+            report_rate,
+            counts_per_report,
+        };
+        assert_eq!(mouse(125.0, 4.0).smoothing_factor(), 1.0);
+        assert_eq!(mouse(500.0, 4.0).smoothing_factor(), 0.25);
+        assert_eq!(mouse(8000.0, 4.0).smoothing_factor(), MIN_FACTOR);
+        assert_eq!(mouse(250.0, 4.0).idle_factor(), 0.5);
+        assert_eq!(mouse(125.0, 1.0).idle_factor(), MAX_IDLE_FACTOR);
+    }
+}
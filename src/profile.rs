@@ -0,0 +1,882 @@
+use crate::config::Config;
+use crate::curve::Curve;
+use crate::deadzone::Deadzone;
+use crate::device_match::{DeviceFilter, DeviceKind, DeviceMatch, DeviceSelector};
+use crate::ff::FfConfig;
+use crate::filter::FilterConfig;
+use crate::gamepad::GamepadConfig;
+use crate::gate::Gate;
+use crate::gesture::GestureConfig;
+use crate::identity::IdentityConfig;
+use crate::keyboard::KeyboardConfig;
+use crate::mapping::{Binding, Input, LayerConfig, Output, WheelConfig};
+use crate::motion::MotionConfig;
+use crate::mouse::DeviceInfo;
+use crate::player::MAX_PLAYERS;
+use crate::probe::MouseCharacteristics;
+use evdev::Key;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Fraction of full stick per inch/second of hand speed.
+/// 0.6 reaches full deflection at ~1.7 in/s — at 800 CPI the same as the
+/// original tuning: a slow ~2/report on a 125Hz mouse (250 counts/s) → ~20%,
+/// a fast flick of ~20/report (2500 counts/s) → full deflection.
+const DEFAULT_SENSITIVITY: f32 = 0.6;
+
+/// CPI assumed for mice without a `cpi` setting.
+pub const DEFAULT_CPI: f32 = 800.0;
+
+/// Milliseconds without mouse data before the filter is forced to zero
+/// (kills the long decay tail).
+const DEFAULT_IDLE_TIMEOUT_MS: f32 = 30.0;
+
+/// Main loop frequency in Hz.
+const DEFAULT_TICK_RATE: u32 = 1000;
+
+/// Supported `tick_rate` range in Hz.
+pub const TICK_RATE_RANGE: std::ops::RangeInclusive<u32> = 250..=8000;
+
+/// Profile picked when neither `--profile` nor `default_profile` is set.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Contents of `config.toml`: a set of named profiles.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    /// Profile used when `--profile` is not given. Falls back to a profile
+    /// named "default" if one exists.
+    pub default_profile: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    /// Per-device settings, shared by all profiles.
+    pub devices: Vec<DeviceConfig>,
+    /// Local multiplayer: one virtual pad per entry. Used when `--profile`
+    /// is not given.
+    pub players: Vec<PlayerConfig>,
+}
+
+/// One player of a multiplayer setup.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// Profile with this player's mice and tuning.
+    pub profile: String,
+}
+
+/// Settings for one physical mouse, matched by evdev name and/or IDs or
+/// a `match` rule. Every given field must match.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    #[serde(rename = "match")]
+    pub rule: Option<DeviceMatch>,
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    /// Counts per inch of the mouse's sensor.
+    pub cpi: Option<f32>,
+}
+
+impl DeviceConfig {
+    pub fn matches(&self, info: &DeviceInfo) -> bool {
+        if self.rule.is_none()
+            && self.name.is_none()
+            && self.vendor.is_none()
+            && self.product.is_none()
+        {
+            return false;
+        }
+        self.rule.as_ref().is_none_or(|m| m.matches(info))
+            && self.name.as_ref().is_none_or(|n| *n == info.name)
+            && self.vendor.is_none_or(|v| v == info.vendor)
+            && self.product.is_none_or(|p| p == info.product)
+    }
+}
+
+/// Everything resolved from the config file and command line.
+pub struct Settings {
+    /// At least one; the first is also what single-player commands use.
+    pub players: Vec<PlayerSettings>,
+    pub devices: Vec<DeviceConfig>,
+    /// `--cpi` from the command line; wins over any device entry.
+    pub cpi_override: Option<f32>,
+    /// Every valid profile in the file by name, in name order, for the
+    /// `next-profile` action.
+    pub profiles: Vec<(String, Profile)>,
+}
+
+/// A player's resolved profile.
+pub struct PlayerSettings {
+    pub profile_name: Option<String>,
+    pub profile: Profile,
+}
+
+impl Settings {
+    pub fn primary(&self) -> &PlayerSettings {
+        &self.players[0]
+    }
+
+    /// CPI for a device: `--cpi`, then its `mice` entry, then the profile,
+    /// then the first matching `[[devices]]` entry, then `DEFAULT_CPI`. The
+    /// bool is false when it fell back to the default.
+    pub fn cpi_for(&self, info: &DeviceInfo, profile: &Profile, mouse: &MouseSpec) -> (f32, bool) {
+        self.cpi_override
+            .or(mouse.cpi)
+            .or(profile.cpi)
+            .or_else(|| {
+                self.devices
+                    .iter()
+                    .filter(|d| d.matches(info))
+                    .find_map(|d| d.cpi)
+            })
+            .map_or((DEFAULT_CPI, false), |c| (c, true))
+    }
+}
+
+/// One named set of tuning values. Every field is optional in the file;
+/// missing fields fall back to the built-in defaults.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Fraction of full stick per inch/second of hand speed.
+    pub sensitivity: f32,
+    /// Mouse CPI for this profile; normally set per device in `[[devices]]`.
+    pub cpi: Option<f32>,
+    pub invert_y: bool,
+    pub stick: Stick,
+    pub filter: FilterConfig,
+    pub idle_timeout_ms: f32,
+    pub tick_rate: u32,
+    /// Scale smoothing time constants to the mouse's report rate.
+    pub auto_tune: bool,
+    /// Known mouse characteristics (from `m2joy probe`); measured at
+    /// startup when absent.
+    pub mouse: Option<MouseCharacteristics>,
+    /// Event node path or match rule; auto-detected when absent.
+    pub device: Option<DeviceSelector>,
+    /// Match rules for devices never to use as the mouse.
+    pub exclude: Vec<DeviceMatch>,
+    /// Several mice at once, each with its own device and settings. When
+    /// empty, a single mouse from `device`.
+    pub mice: Vec<MouseConfig>,
+    pub buttons: ButtonMap,
+    /// Any mouse button or wheel direction to any output; wins over
+    /// `buttons`.
+    pub map: HashMap<Input, Binding>,
+    pub wheel: WheelConfig,
+    pub gestures: GestureConfig,
+    /// Named binding sets switched on by a modifier button.
+    pub layers: HashMap<String, LayerConfig>,
+    /// A keyboard grabbed along with the mouse; none when absent.
+    pub keyboard: Option<KeyboardConfig>,
+    /// A physical gamepad merged into the virtual pad; none when absent.
+    pub gamepad: Option<GamepadConfig>,
+    /// Where games' force feedback goes. Read at startup, when the pad is
+    /// created.
+    pub ff: FfConfig,
+    /// The controller the virtual pad passes for. Read at startup, when the
+    /// pad is created.
+    pub identity: IdentityConfig,
+    /// The mouse as a gyro on a motion sensor device; none when absent.
+    /// Read at startup, when the pad is created.
+    pub motion: Option<MotionConfig>,
+    pub curve: Curve,
+    pub deadzone: Deadzone,
+    pub gate: Gate,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            sensitivity: DEFAULT_SENSITIVITY,
+            cpi: None,
+            invert_y: false,
+            stick: Stick::Right,
+            filter: FilterConfig::default(),
+            idle_timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            tick_rate: DEFAULT_TICK_RATE,
+            auto_tune: true,
+            mouse: None,
+            device: None,
+            exclude: Vec::new(),
+            mice: Vec::new(),
+            buttons: ButtonMap::default(),
+            map: HashMap::new(),
+            wheel: WheelConfig::default(),
+            gestures: GestureConfig::default(),
+            layers: HashMap::new(),
+            keyboard: None,
+            gamepad: None,
+            ff: FfConfig::default(),
+            identity: IdentityConfig::default(),
+            motion: None,
+            curve: Curve::default(),
+            deadzone: Deadzone::default(),
+            gate: Gate::default(),
+        }
+    }
+}
+
+/// One entry of a profile's `mice` list. Unset fields take the profile's
+/// value.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MouseConfig {
+    pub device: Option<DeviceSelector>,
+    pub stick: Option<Stick>,
+    pub sensitivity: Option<f32>,
+    pub cpi: Option<f32>,
+    pub invert_y: Option<bool>,
+}
+
+/// A mouse to open, with the profile's defaults filled in.
+#[derive(Debug, Clone)]
+pub struct MouseSpec {
+    pub device: Option<DeviceSelector>,
+    pub stick: Stick,
+    pub sensitivity: f32,
+    /// CPI from the `mice` entry; see `Settings::cpi_for` for the fallbacks.
+    pub cpi: Option<f32>,
+    pub invert_y: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    pub const ALL: [Stick; 2] = [Stick::Left, Stick::Right];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stick::Left => "left",
+            Stick::Right => "right",
+        }
+    }
+}
+
+/// Which pad button each mouse button is forwarded to.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonMap {
+    pub left: PadButton,
+    pub right: PadButton,
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self {
+            left: PadButton::R2,
+            right: PadButton::L2,
+        }
+    }
+}
+
+/// Buttons of the virtual gamepad, including the D-pad directions.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    L1,
+    R1,
+    L2,
+    R2,
+    L3,
+    R3,
+    Select,
+    Start,
+    Mode,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl PadButton {
+    pub const ALL: [PadButton; 17] = [
+        PadButton::South,
+        PadButton::East,
+        PadButton::North,
+        PadButton::West,
+        PadButton::L1,
+        PadButton::R1,
+        PadButton::L2,
+        PadButton::R2,
+        PadButton::L3,
+        PadButton::R3,
+        PadButton::Select,
+        PadButton::Start,
+        PadButton::Mode,
+        PadButton::Up,
+        PadButton::Down,
+        PadButton::Left,
+        PadButton::Right,
+    ];
+
+    /// Key code, or None for D-pad directions (reported on the hat axes).
+    pub fn key(self) -> Option<Key> {
+        Some(match self {
+            PadButton::South => Key::BTN_SOUTH,
+            PadButton::East => Key::BTN_EAST,
+            PadButton::North => Key::BTN_NORTH,
+            PadButton::West => Key::BTN_WEST,
+            PadButton::L1 => Key::BTN_TL,
+            PadButton::R1 => Key::BTN_TR,
+            PadButton::L2 => Key::BTN_TL2,
+            PadButton::R2 => Key::BTN_TR2,
+            PadButton::L3 => Key::BTN_THUMBL,
+            PadButton::R3 => Key::BTN_THUMBR,
+            PadButton::Select => Key::BTN_SELECT,
+            PadButton::Start => Key::BTN_START,
+            PadButton::Mode => Key::BTN_MODE,
+            PadButton::Up | PadButton::Down | PadButton::Left | PadButton::Right => return None,
+        })
+    }
+}
+
+/// Default config location: `$XDG_CONFIG_HOME/m2joy/config.toml`,
+/// falling back to `~/.config/m2joy/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("m2joy/config.toml"))
+}
+
+impl Profile {
+    /// The mice to open: the `mice` entries, or the single `device`.
+    pub fn mice(&self) -> Vec<MouseSpec> {
+        let single = [MouseConfig {
+            device: self.device.clone(),
+            ..MouseConfig::default()
+        }];
+        let entries = if self.mice.is_empty() {
+            &single[..]
+        } else {
+            &self.mice[..]
+        };
+        entries
+            .iter()
+            .map(|m| MouseSpec {
+                device: m.device.clone(),
+                stick: m.stick.unwrap_or(self.stick),
+                sensitivity: m.sensitivity.unwrap_or(self.sensitivity),
+                cpi: m.cpi,
+                invert_y: m.invert_y.unwrap_or(self.invert_y),
+            })
+            .collect()
+    }
+
+    /// Auto-detection filter for one mouse: its `device` rule and the
+    /// profile's `exclude` list.
+    pub fn device_filter(&self, mouse: &MouseSpec) -> DeviceFilter {
+        self.filter(DeviceKind::Mouse, &mouse.device)
+    }
+
+    /// Auto-detection filter for the keyboard, if the profile has one.
+    pub fn keyboard_filter(&self) -> Option<DeviceFilter> {
+        let keyboard = self.keyboard.as_ref()?;
+        Some(self.filter(DeviceKind::Keyboard, &keyboard.device))
+    }
+
+    /// Auto-detection filter for the gamepad, if the profile has one.
+    pub fn gamepad_filter(&self) -> Option<DeviceFilter> {
+        let gamepad = self.gamepad.as_ref()?;
+        Some(self.filter(DeviceKind::Gamepad, &gamepad.device))
+    }
+
+    fn filter(&self, kind: DeviceKind, device: &Option<DeviceSelector>) -> DeviceFilter {
+        DeviceFilter {
+            kind,
+            rule: match device {
+                Some(DeviceSelector::Match(m)) => Some(m.clone()),
+                _ => None,
+            },
+            exclude: self.exclude.clone(),
+        }
+    }
+
+    /// Every input binding: `buttons` for left and right click, with `map`
+    /// on top.
+    pub fn bindings(&self) -> HashMap<Input, Binding> {
+        let mut bindings = HashMap::from([
+            (
+                Input::Key(Key::BTN_LEFT),
+                Output::Button(self.buttons.left).into(),
+            ),
+            (
+                Input::Key(Key::BTN_RIGHT),
+                Output::Button(self.buttons.right).into(),
+            ),
+        ]);
+        bindings.extend(self.map.iter().map(|(&i, &b)| (i, b)));
+        bindings
+    }
+
+    /// Check values that deserialize fine but make no sense.
+    pub fn validate(&self) -> Result<(), String> {
+        if !TICK_RATE_RANGE.contains(&self.tick_rate) {
+            return Err(format!(
+                "tick_rate must be {}-{} Hz",
+                TICK_RATE_RANGE.start(),
+                TICK_RATE_RANGE.end()
+            ));
+        }
+        if self.cpi.is_some_and(|c| c <= 0.0) {
+            return Err("cpi must be > 0".into());
+        }
+        if self.idle_timeout_ms <= 0.0 {
+            return Err("idle_timeout_ms must be > 0".into());
+        }
+        if let Some(m) = &self.mouse {
+            m.validate()?;
+        }
+        for (i, m) in self.mice.iter().enumerate() {
+            if m.sensitivity.is_some_and(|s| s <= 0.0) || m.cpi.is_some_and(|c| c <= 0.0) {
+                return Err(format!("mice[{}]: sensitivity and cpi must be > 0", i));
+            }
+            if self.mice.len() > 1 && m.device.is_none() {
+                return Err(format!(
+                    "mice[{}]: device is required when using several mice",
+                    i
+                ));
+            }
+        }
+        for (input, binding) in &self.map {
+            binding
+                .validate(*input)
+                .map_err(|e| format!("map.{}: {}", input, e))?;
+        }
+        for (name, layer) in &self.layers {
+            layer
+                .validate()
+                .map_err(|e| format!("layers.{}: {}", name, e))?;
+        }
+        self.wheel.validate()?;
+        self.gestures.validate()?;
+        if let Some(keyboard) = &self.keyboard {
+            keyboard.validate()?;
+        }
+        self.ff.validate()?;
+        self.identity.validate()?;
+        if let Some(motion) = &self.motion {
+            motion.validate()?;
+        }
+        self.filter.validate()?;
+        self.curve.validate()?;
+        self.deadzone.validate()?;
+        self.gate.validate()
+    }
+}
+
+impl FileConfig {
+    /// Load and parse a config file. A missing file is not an error.
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&text).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+}
+
+/// Config file in use: `--config` if given, otherwise the XDG default.
+pub fn config_path(config: &Config) -> Option<PathBuf> {
+    config.config.clone().or_else(default_config_path)
+}
+
+/// Look up a profile (the defaults if `name` is None) and apply the CLI
+/// flags on top.
+fn load_profile(
+    file: &FileConfig,
+    name: Option<&str>,
+    path: Option<&std::path::Path>,
+    config: &Config,
+) -> std::io::Result<Profile> {
+    let mut profile = match name {
+        Some(n) => file.profiles.get(n).cloned().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "profile '{}' not found in {}",
+                    n,
+                    path.map_or("<none>".into(), |p| p.display().to_string())
+                ),
+            )
+        })?,
+        None => Profile::default(),
+    };
+
+    // Sensitivity and inversion flags apply to every mouse; `--device`
+    // replaces the `mice` list with that one device.
+    if let Some(s) = config.sensitivity {
+        profile.sensitivity = s;
+        profile.mice.iter_mut().for_each(|m| m.sensitivity = None);
+    }
+    if let Some(invert) = config.invert_y {
+        profile.invert_y = invert;
+        profile.mice.iter_mut().for_each(|m| m.invert_y = None);
+    }
+    if let Some(left) = config.left_stick {
+        profile.stick = if left { Stick::Left } else { Stick::Right };
+    }
+    if let Some(d) = &config.device {
+        profile.device = Some(d.clone());
+        profile.mice.clear();
+    }
+    profile.exclude.extend(config.exclude.iter().cloned());
+    if let Some(r) = config.tick_rate {
+        profile.tick_rate = r;
+    }
+
+    profile.validate().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("profile '{}': {}", name.unwrap_or("(defaults)"), e),
+        )
+    })?;
+    Ok(profile)
+}
+
+/// Resolve the active profile(s): config file first, then CLI flags on top.
+pub fn resolve(config: &Config) -> std::io::Result<Settings> {
+    let path = config_path(config);
+    let file = match &path {
+        Some(p) => FileConfig::load(p)?,
+        None => FileConfig::default(),
+    };
+
+    let names: Vec<Option<String>> = if config.profile.is_none() && !file.players.is_empty() {
+        if file.players.len() > MAX_PLAYERS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("at most {} players are supported", MAX_PLAYERS),
+            ));
+        }
+        if config.device.is_some() && file.players.len() > 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--device needs a single player; pick one with --profile",
+            ));
+        }
+        file.players
+            .iter()
+            .map(|p| Some(p.profile.clone()))
+            .collect()
+    } else {
+        vec![config
+            .profile
+            .clone()
+            .or(file.default_profile.clone())
+            .or_else(|| {
+                file.profiles
+                    .contains_key(DEFAULT_PROFILE)
+                    .then(|| DEFAULT_PROFILE.to_string())
+            })]
+    };
+    let players = names
+        .into_iter()
+        .map(|name| {
+            let profile = load_profile(&file, name.as_deref(), path.as_deref(), config)?;
+            Ok(PlayerSettings {
+                profile_name: name,
+                profile,
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    // A broken profile no player starts with only matters once someone
+    // switches to it.
+    let mut all: Vec<&String> = file.profiles.keys().collect();
+    all.sort();
+    let profiles = all
+        .into_iter()
+        .filter_map(
+            |n| match load_profile(&file, Some(n), path.as_deref(), config) {
+                Ok(p) => Some((n.clone(), p)),
+                Err(e) => {
+                    log::warn!("Not switchable: {}", e);
+                    None
+                }
+            },
+        )
+        .collect();
+
+    if let Some(d) = file
+        .devices
+        .iter()
+        .find(|d| d.cpi.is_some_and(|c| c <= 0.0))
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "device {:?}: cpi must be > 0",
+                d.name.as_deref().unwrap_or("?")
+            ),
+        ));
+    }
+    if config.cpi.is_some_and(|c| c <= 0.0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--cpi must be > 0",
+        ));
+    }
+
+    Ok(Settings {
+        players,
+        devices: file.devices,
+        cpi_override: config.cpi,
+        profiles,
+    })
+}
+
+/// Edit one profile table in the config file in place, keeping the user's
+/// comments and formatting. Creates the file, the profile and any missing
+/// parent tables as needed.
+pub fn update_profile(
+    path: &std::path::Path,
+    name: &str,
+    edit: impl FnOnce(&mut toml_edit::Table) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })?;
+
+    let profiles = doc
+        .entry("profiles")
+        .or_insert_with(|| {
+            let mut t = toml_edit::Table::new();
+            t.set_implicit(true);
+            toml_edit::Item::Table(t)
+        })
+        .as_table_mut()
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "'profiles' is not a table")
+        })?;
+    let table = profiles
+        .entry(name)
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("profile '{}' is not a table", name),
+            )
+        })?;
+    edit(table)?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside and renamed, so a crash never leaves half a config
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, doc.to_string())?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Resolve `args` against a config file holding `file`; `name` keeps
+    /// each test's file apart.
+    fn resolve_with(name: &str, file: &str, args: &[&str]) -> std::io::Result<Profile> {
+        let dir = std::env::temp_dir().join(format!("m2joy-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, file).unwrap();
+        let mut argv = vec!["m2joy", "--config", path.to_str().unwrap()];
+        argv.extend(args);
+        let result = resolve(&Config::parse_from(argv));
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.map(|mut s| s.players.remove(0).profile)
+    }
+
+    const TUNED: &str = r#"
+default_profile = "tuned"
+[profiles.tuned]
+sensitivity = 0.3
+invert_y = true
+stick = "left"
+"#;
+
+    #[test]
+    fn profile_values_apply_without_flags() {
+        let p = resolve_with("profile-values", TUNED, &[]).unwrap();
+        assert_eq!(p.sensitivity, 0.3);
+        assert!(p.invert_y);
+        assert_eq!(p.stick, Stick::Left);
+    }
+
+    #[test]
+    fn flags_override_the_profile_both_ways() {
+        let args = [
+            "--sensitivity",
+            "0.9",
+            "--invert-y=false",
+            "--left-stick=false",
+        ];
+        let p = resolve_with("flags-off", TUNED, &args).unwrap();
+        assert_eq!(p.sensitivity, 0.9);
+        assert!(!p.invert_y);
+        assert_eq!(p.stick, Stick::Right);
+
+        let p = resolve_with("flags-on", "", &["--invert-y", "--left-stick"]).unwrap();
+        assert!(p.invert_y);
+        assert_eq!(p.stick, Stick::Left);
+    }
+
+    #[test]
+    fn profile_is_picked_by_flag_then_default_profile() {
+        let file = r#"
+default_profile = "b"
+[profiles.a]
+sensitivity = 0.1
+[profiles.b]
+sensitivity = 0.2
+"#;
+        let p = resolve_with("pick-default", file, &[]).unwrap();
+        assert_eq!(p.sensitivity, 0.2);
+        let p = resolve_with("pick-flag", file, &["--profile", "a"]).unwrap();
+        assert_eq!(p.sensitivity, 0.1);
+        let e = resolve_with("pick-missing", file, &["--profile", "c"]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn bad_profiles_are_rejected() {
+        for (name, file) in [
+            ("bad-field", "[profiles.default]\nspeed = 2\n"),
+            ("bad-type", "[profiles.default]\ninvert_y = \"yes\"\n"),
+            ("bad-tick-rate", "[profiles.default]\ntick_rate = 100\n"),
+        ] {
+            let e = resolve_with(name, file, &[]).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn update_profile_keeps_the_rest_of_the_file() {
+        let dir = std::env::temp_dir().join(format!("m2joy-update-{}", std::process::id()));
+        let path = dir.join("m2joy/config.toml");
+        // Creates the file and its directory
+        update_profile(&path, "default", |t| {
+            t.insert("sensitivity", toml_edit::value(0.5));
+            Ok(())
+        })
+        .unwrap();
+
+        let edited = "# my tuning\n[profiles.default]\nsensitivity = 0.5 # slow\n\n[profiles.other]\ninvert_y = true\n";
+        std::fs::write(&path, edited).unwrap();
+        update_profile(&path, "other", |t| {
+            t.insert("sensitivity", toml_edit::value(0.7));
+            Ok(())
+        })
+        .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# my tuning\n[profiles.default]\nsensitivity = 0.5 # slow\n"));
+        let file = FileConfig::load(&path).unwrap();
+        assert_eq!(file.profiles["default"].sensitivity, 0.5);
+        assert_eq!(file.profiles["other"].sensitivity, 0.7);
+        assert!(file.profiles["other"].invert_y);
+        assert!(!dir.join("m2joy/config.toml.tmp").exists());
+
+        std::fs::write(&path, "profiles = 3\n").unwrap();
+        assert!(update_profile(&path, "default", |_| Ok(())).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "profiles = 3\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn g_pro() -> DeviceInfo {
+        DeviceInfo {
+            name: "Logitech G Pro".into(),
+            vendor: 0x046d,
+            product: 0xc08b,
+            phys: "usb-0000:00:14.0-2/input0".into(),
+            uniq: String::new(),
+        }
+    }
+
+    fn device(entry: &str) -> DeviceConfig {
+        toml::from_str(entry).unwrap()
+    }
+
+    #[test]
+    fn device_entries_match_on_every_given_field() {
+        let info = g_pro();
+        assert!(device("name = \"Logitech G Pro\"").matches(&info));
+        assert!(device("vendor = 0x046d\nproduct = 0xc08b").matches(&info));
+        assert!(device("match = \"name~=g pro\"\nvendor = 0x046d").matches(&info));
+        assert!(!device("name = \"Logitech G Pro\"\nproduct = 1").matches(&info));
+        assert!(!device("match = \"phys~=usb-1\"").matches(&info));
+        // An entry with nothing to match on matches nothing
+        assert!(!device("cpi = 1600").matches(&info));
+    }
+
+    #[test]
+    fn cpi_comes_from_the_most_specific_setting() {
+        let mut settings = Settings {
+            players: Vec::new(),
+            devices: vec![
+                device("vendor = 0x1234\ncpi = 400"),
+                device("vendor = 0x046d"),
+                device("name = \"Logitech G Pro\"\ncpi = 1600"),
+                device("vendor = 0x046d\ncpi = 3200"),
+            ],
+            cpi_override: None,
+            profiles: Vec::new(),
+        };
+        let mut profile = Profile::default();
+        let mut mouse = profile.mice().remove(0);
+        let info = g_pro();
+        // The first matching entry with a CPI
+        assert_eq!(settings.cpi_for(&info, &profile, &mouse), (1600.0, true));
+        profile.cpi = Some(1000.0);
+        assert_eq!(settings.cpi_for(&info, &profile, &mouse), (1000.0, true));
+        mouse.cpi = Some(1200.0);
+        assert_eq!(settings.cpi_for(&info, &profile, &mouse), (1200.0, true));
+        settings.cpi_override = Some(2000.0);
+        assert_eq!(settings.cpi_for(&info, &profile, &mouse), (2000.0, true));
+
+        let other = DeviceInfo {
+            name: "Trackball".into(),
+            vendor: 0x047d,
+            ..g_pro()
+        };
+        let settings = Settings {
+            cpi_override: None,
+            ..settings
+        };
+        let profile = Profile::default();
+        let mouse = profile.mice().remove(0);
+        assert_eq!(
+            settings.cpi_for(&other, &profile, &mouse),
+            (DEFAULT_CPI, false)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Lock-free single-producer/single-consumer ring buffer.
+///
+/// The reader thread pushes, the main loop pops; neither side ever blocks.
+/// Splitting into a non-cloneable `Producer` and `Consumer` makes the
+/// single-producer/single-consumer contract a compile-time guarantee.
+struct Ring<T> {
+    buf: Box<[UnsafeCell<T>]>,
+    mask: usize,
+    /// Next slot to write; only the producer stores it.
+    head: AtomicUsize,
+    /// Next slot to read; only the consumer stores it.
+    tail: AtomicUsize,
+}
+
+// SAFETY: slots are handed between threads through the head/tail
+// release/acquire pairs, and `Producer` and `Consumer` are the only handles,
+// so each slot has one writer or one reader at a time.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// Create a ring holding up to `capacity` items (rounded up to a power of two).
+pub fn channel<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.next_power_of_two();
+    let buf = (0..capacity)
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect();
+    let ring = Arc::new(Ring {
+        buf,
+        mask: capacity - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+        },
+        Consumer { ring },
+    )
+}
+
+impl<T: Copy> Producer<T> {
+    /// Append an item. Returns false (dropping it) if the ring is full.
+    pub fn push(&mut self, item: T) -> bool {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) > ring.mask {
+            return false;
+        }
+        // SAFETY: the slot at `head` is outside [tail, head), so the consumer
+        // is not reading it, and only this producer writes.
+        unsafe { *ring.buf[head & ring.mask].get() = item };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Take the oldest item, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail == head {
+            return None;
+        }
+        // SAFETY: the slot at `tail` is inside [tail, head), published by the
+        // producer's release store, and it will not be rewritten until we
+        // advance `tail`.
+        let item = unsafe { *ring.buf[tail & ring.mask].get() };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Iterator that pops until the ring is empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_in_push_order() {
+        let (mut tx, mut rx) = channel(4);
+        assert_eq!(rx.pop(), None);
+        for i in 1..=3 {
+            assert!(tx.push(i));
+        }
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(rx.pop(), Some(2));
+        assert_eq!(rx.pop(), Some(3));
+        assert_eq!(rx.pop(), None);
+    }
+
+    #[test]
+    fn capacity_rounds_up_and_full_ring_drops() {
+        let (mut tx, mut rx) = channel(3);
+        for i in 0..4 {
+            assert!(tx.push(i));
+        }
+        assert!(!tx.push(4));
+        assert_eq!(rx.pop(), Some(0));
+        // One slot free again
+        assert!(tx.push(5));
+        assert!(!tx.push(6));
+        assert_eq!(rx.drain().collect::<Vec<_>>(), [1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (mut tx, mut rx) = channel(4);
+        for round in 0..10 {
+            for i in 0..3 {
+                assert!(tx.push(round * 3 + i));
+            }
+            let got: Vec<_> = rx.drain().collect();
+            assert_eq!(got, [round * 3, round * 3 + 1, round * 3 + 2]);
+        }
+    }
+
+    #[test]
+    fn drain_stops_when_empty() {
+        let (mut tx, mut rx) = channel::<u32>(8);
+        assert_eq!(rx.drain().count(), 0);
+        tx.push(7);
+        assert_eq!(rx.drain().collect::<Vec<_>>(), [7]);
+        assert_eq!(rx.drain().count(), 0);
+    }
+
+    #[test]
+    fn two_threads_keep_order() {
+        const N: u64 = 100_000;
+        let (mut tx, mut rx) = channel(64);
+        let producer = std::thread::spawn(move || {
+            for i in 0..N {
+                while !tx.push(i) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut next = 0;
+        while next < N {
+            match rx.pop() {
+                Some(i) => {
+                    assert_eq!(i, next);
+                    next += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(rx.pop(), None);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::ff::{self, FfCommand, FfSender};
+use crate::filter::Filter;
+use crate::gamepad::{self, PadAxes, Physical};
+use crate::identity::Preset;
+use crate::mapping::Input;
+use crate::mouse::{
+    monotonic_now, ButtonEvent, Sample, SourceReader, SourceState, EVENT_RING_CAPACITY,
+    SAMPLE_RING_CAPACITY,
+};
+use crate::probe::{MouseCharacteristics, ProbeStats};
+use crate::profile::{MouseSpec, Profile, Settings, Stick};
+use crate::ring::{self, Consumer};
+use crate::velocity::ReportVelocity;
+use evdev::{AttributeSet, FFEffectType};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// A keyboard feeding the pad: only its keys, through the same reader as
+/// a mouse, grabbed and released along with the player's mice.
+pub struct KeyboardSource {
+    pub name: String,
+    pub state: Arc<SourceState>,
+    thread: JoinHandle<()>,
+    keys: Consumer<ButtonEvent>,
+    /// Key events drained this tick.
+    pub events: Vec<ButtonEvent>,
+}
+
+impl KeyboardSource {
+    pub fn open(path: &str, active: Arc<AtomicBool>) -> std::io::Result<Self> {
+        let state = Arc::new(SourceState::new(active));
+        let (key_tx, key_rx) = ring::channel(EVENT_RING_CAPACITY);
+        let mut reader = SourceReader::keyboard(path, Arc::clone(&state))?.with_buttons(key_tx);
+        let name = reader.info().name.clone();
+        let thread = std::thread::Builder::new()
+            .name("keyboard-reader".into())
+            .spawn(move || reader.run())?;
+        Ok(Self {
+            name,
+            state,
+            thread,
+            keys: key_rx,
+            events: Vec::with_capacity(EVENT_RING_CAPACITY),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Key events since the last tick.
+    pub fn drain(&mut self) {
+        self.events.clear();
+        self.events.extend(self.keys.drain());
+    }
+
+    /// Stop the reader thread and wait for it to release the keyboard.
+    pub fn stop(self) {
+        self.state.quit.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// A physical gamepad merged into the pad: its buttons and axes as they
+/// are now, and the way back for force feedback.
+pub struct GamepadSource {
+    pub name: String,
+    pub state: Arc<SourceState>,
+    thread: JoinHandle<()>,
+    buttons: Consumer<ButtonEvent>,
+    axes: Consumer<PadAxes>,
+    pub physical: Physical,
+    /// The virtual pad's preset, which its buttons are translated for.
+    preset: Preset,
+    /// Effect types it plays, offered on the virtual pad.
+    pub ff_types: Option<AttributeSet<FFEffectType>>,
+    ff: Option<FfSender>,
+}
+
+impl GamepadSource {
+    pub fn open(path: &str, active: Arc<AtomicBool>, preset: Preset) -> std::io::Result<Self> {
+        let state = Arc::new(SourceState::new(active));
+        let (button_tx, button_rx) = ring::channel(EVENT_RING_CAPACITY);
+        let (axes_tx, axes_rx) = ring::channel(SAMPLE_RING_CAPACITY);
+        let mut reader = SourceReader::gamepad(path, Arc::clone(&state))?
+            .with_buttons(button_tx)
+            .with_axes(axes_tx);
+        let name = reader.info().name.clone();
+        let ff_types = reader.force_feedback();
+        let ff = match ff_types {
+            Some(_) => {
+                let (tx, rx) = ff::channel()?;
+                reader = reader.with_ff(rx);
+                Some(tx)
+            }
+            None => None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("gamepad-reader".into())
+            .spawn(move || reader.run())?;
+        Ok(Self {
+            name,
+            state,
+            thread,
+            buttons: button_rx,
+            axes: axes_rx,
+            physical: Physical::default(),
+            preset,
+            ff_types,
+            ff,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Bring `physical` up to date.
+    pub fn drain(&mut self) {
+        for event in self.buttons.drain() {
+            let Input::Key(key) = event.input else {
+                continue;
+            };
+            if let Some(button) = gamepad::pad_button(key, self.preset) {
+                let bit = 1 << button as u32;
+                if event.value != 0 {
+                    self.physical.buttons |= bit;
+                } else {
+                    self.physical.buttons &= !bit;
+                }
+            }
+        }
+        if let Some(axes) = self.axes.drain().last() {
+            self.physical.axes = axes;
+        }
+    }
+
+    /// Play a force feedback request on the pad, if it has any.
+    pub fn forward_ff(&self, command: FfCommand) {
+        if let Some(ff) = &self.ff {
+            ff.send(command);
+        }
+    }
+
+    /// Forget what the pad held, e.g. when released.
+    pub fn reset(&mut self) {
+        self.physical = Physical::default();
+    }
+
+    /// Stop the reader thread and wait for it to release the pad.
+    pub fn stop(self) {
+        self.state.quit.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// One mouse feeding the pad: its reader thread and its own chain from
+/// report timestamps to a stick deflection.
+pub struct MouseSource {
+    pub name: String,
+    pub stick: Stick,
+    pub state: Arc<SourceState>,
+    thread: JoinHandle<()>,
+    samples: Consumer<Sample>,
+    /// Reports drained this tick.
+    batch: Vec<Sample>,
+    buttons: Consumer<ButtonEvent>,
+    /// Button and wheel events drained this tick.
+    pub events: Vec<ButtonEvent>,
+    report_velocity: ReportVelocity,
+    filter: Box<dyn Filter>,
+    idle_timeout: f32,
+    idle_time: f32,
+    probe_stats: Option<ProbeStats>,
+    cpi: f32,
+    /// Counts/s → inches/s → fraction of full deflection under the linear
+    /// mapping.
+    scale: f32,
+    y_sign: f32,
+    /// Filtered velocity in counts/s, for debug output.
+    pub vel: (f32, f32),
+    pub dbg_raw: (i64, i64),
+    pub dbg_samples: u32,
+}
+
+impl MouseSource {
+    /// Open the mouse here so a failure stops startup, then hand the reader
+    /// to its own thread.
+    pub fn open(
+        path: &str,
+        spec: &MouseSpec,
+        profile: &Profile,
+        settings: &Settings,
+        active: Arc<AtomicBool>,
+    ) -> std::io::Result<Self> {
+        let state = Arc::new(SourceState::new(active));
+        let (sample_tx, sample_rx) = ring::channel(SAMPLE_RING_CAPACITY);
+        let (button_tx, button_rx) = ring::channel(EVENT_RING_CAPACITY);
+        let mut reader =
+            SourceReader::new(path, Arc::clone(&state), sample_tx)?.with_buttons(button_tx);
+
+        let name = reader.info().name.clone();
+        let (cpi, cpi_known) = settings.cpi_for(reader.info(), profile, spec);
+        if cpi_known {
+            log::info!("Mouse CPI for '{}': {}", name, cpi);
+        } else {
+            log::info!(
+                "No CPI configured for '{}', assuming {} (set --cpi or a [[devices]] entry)",
+                name,
+                cpi
+            );
+        }
+
+        let thread = std::thread::Builder::new()
+            .name("mouse-reader".into())
+            .spawn(move || reader.run())?;
+
+        // Smoothing constants follow the mouse's report rate: taken from the
+        // profile's `mouse` table, or measured during the first seconds of motion.
+        let (filter, idle_timeout) = tuned_filter(profile, profile.mouse);
+        Ok(Self {
+            name,
+            stick: spec.stick,
+            state,
+            thread,
+            samples: sample_rx,
+            batch: Vec::with_capacity(SAMPLE_RING_CAPACITY),
+            buttons: button_rx,
+            events: Vec::with_capacity(EVENT_RING_CAPACITY),
+            report_velocity: ReportVelocity::new(idle_timeout),
+            filter,
+            idle_timeout,
+            idle_time: 0.0,
+            probe_stats: (profile.auto_tune && profile.mouse.is_none()).then(ProbeStats::default),
+            cpi,
+            scale: spec.sensitivity / cpi,
+            y_sign: if spec.invert_y { -1.0 } else { 1.0 },
+            vel: (0.0, 0.0),
+            dbg_raw: (0, 0),
+            dbg_samples: 0,
+        })
+    }
+
+    /// Whether this mouse is currently delivering input.
+    pub fn connected(&self) -> bool {
+        self.state.connected.load(Ordering::Relaxed)
+    }
+
+    /// The reader thread exited on its own (only happens on a fatal error).
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Reports since the last tick, also consumed when inactive so nothing
+    /// stale is left in the ring.
+    pub fn drain(&mut self) {
+        self.batch.clear();
+        self.batch.extend(self.samples.drain());
+        self.events.clear();
+        self.events.extend(self.buttons.drain());
+    }
+
+    /// Counts this tick, y inverted like the stick when configured.
+    pub fn counts(&self) -> (i32, i32) {
+        let y_sign = self.y_sign as i32;
+        self.batch
+            .iter()
+            .fold((0, 0), |(x, y), s| (x + s.dx, y + s.dy * y_sign))
+    }
+
+    /// Take another profile's tuning. The device and its CPI stay.
+    pub fn retune(&mut self, spec: &MouseSpec, profile: &Profile) {
+        self.stick = spec.stick;
+        self.scale = spec.sensitivity / self.cpi;
+        self.y_sign = if spec.invert_y { -1.0 } else { 1.0 };
+        (self.filter, self.idle_timeout) = tuned_filter(profile, profile.mouse);
+        self.report_velocity.set_max_gap(self.idle_timeout);
+        self.probe_stats = (profile.auto_tune && profile.mouse.is_none()).then(ProbeStats::default);
+        self.reset();
+    }
+
+    /// Multiply the sensitivity, e.g. for `sensitivity-up`.
+    pub fn scale_sensitivity(&mut self, factor: f32) {
+        self.scale *= factor;
+    }
+
+    /// Advance by `dt` seconds and return the stick deflection as a fraction
+    /// of full, before the curve.
+    pub fn update(&mut self, dt: f32, profile: &Profile, debug: bool) -> (f32, f32) {
+        if debug {
+            for s in &self.batch {
+                self.dbg_raw.0 += s.dx as i64;
+                self.dbg_raw.1 += s.dy as i64;
+            }
+            self.dbg_samples += self.batch.len() as u32;
+        }
+
+        if let Some(stats) = &mut self.probe_stats {
+            self.batch.iter().for_each(|s| stats.add(s));
+            if let Some(m) = stats.estimate() {
+                log::info!(
+                    "Detected '{}': ~{:.0} Hz, ~{:.1} counts/report",
+                    self.name,
+                    m.report_rate,
+                    m.counts_per_report
+                );
+                (self.filter, self.idle_timeout) = tuned_filter(profile, Some(m));
+                self.report_velocity.set_max_gap(self.idle_timeout);
+                self.probe_stats = None;
+            }
+        }
+
+        // Velocity from kernel report timestamps, handed to the filter as
+        // the counts it implies for this tick.
+        let (rx, ry) = self.report_velocity.update(&self.batch, monotonic_now());
+        self.vel = self.filter.update(rx * dt, ry * self.y_sign * dt, dt);
+
+        // Track idle time to force quick stop when mouse stops
+        if rx == 0.0 && ry == 0.0 {
+            self.idle_time += dt;
+        } else {
+            self.idle_time = 0.0;
+        }
+
+        // After idle_timeout_ms of no mouse data, force zero (kills the long decay tail)
+        if self.idle_time > self.idle_timeout {
+            self.filter.reset();
+            self.vel = (0.0, 0.0);
+        }
+
+        (self.vel.0 * self.scale, self.vel.1 * self.scale)
+    }
+
+    /// Forget all motion, e.g. while released.
+    pub fn reset(&mut self) {
+        self.filter.reset();
+        self.report_velocity.reset();
+        self.vel = (0.0, 0.0);
+        self.idle_time = 0.0;
+    }
+
+    /// Estimated report rate in Hz, for debug output.
+    pub fn report_rate(&self) -> f32 {
+        1.0 / self.report_velocity.period()
+    }
+
+    /// Stop the reader thread and wait for it to release the device.
+    pub fn stop(self) {
+        self.state.quit.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// Build the profile's filter and idle timeout (in seconds), scaled for the
+/// given mouse when auto-tuning is enabled.
+fn tuned_filter(profile: &Profile, mouse: Option<MouseCharacteristics>) -> (Box<dyn Filter>, f32) {
+    let idle_timeout = profile.idle_timeout_ms / 1000.0;
+    match mouse.filter(|_| profile.auto_tune) {
+        Some(m) => (
+            profile.filter.scaled(m.smoothing_factor()).build(),
+            idle_timeout * m.idle_factor(),
+        ),
+        None => (profile.filter.build(), idle_timeout),
+    }
+}
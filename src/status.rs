@@ -0,0 +1,81 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// File the running instance keeps its players' state in, for `m2joy status`.
+pub fn path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("m2joy.status"),
+        _ => PathBuf::from(format!("/tmp/m2joy-{}.status", unsafe { libc::getuid() })),
+    }
+}
+
+/// Replace the status file with `lines`. Written aside and renamed, so a
+/// reader never sees half of it.
+pub fn write(lines: &[String]) {
+    let path = path();
+    let tmp = path.with_extension("status.tmp");
+    let result = create_aside(&tmp)
+        .and_then(|mut f| f.write_all((lines.join("\n") + "\n").as_bytes()))
+        .and_then(|_| std::fs::rename(&tmp, &path));
+    if let Err(e) = result {
+        log::warn!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Create the file to write aside afresh. In the shared /tmp fallback
+/// another user may have left a file or a symlink at its name; neither is
+/// followed or written to.
+fn create_aside(tmp: &Path) -> std::io::Result<File> {
+    match std::fs::remove_file(tmp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(tmp)
+}
+
+pub fn remove() {
+    let _ = std::fs::remove_file(path());
+}
+
+/// Print the running instance's status, or exit with an error.
+pub fn print() {
+    if crate::find_running_instance().is_none() {
+        eprintln!("No running m2joy instance found");
+        std::process::exit(1);
+    }
+    match std::fs::read_to_string(path()) {
+        Ok(s) => print!("{}", s),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path().display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aside_file_replaces_a_symlink_without_following_it() {
+        let dir = std::env::temp_dir().join(format!("m2joy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target");
+        let tmp = dir.join("status.tmp");
+        std::fs::write(&target, "keep").unwrap();
+        std::os::unix::fs::symlink(&target, &tmp).unwrap();
+
+        create_aside(&tmp).unwrap().write_all(b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+        assert!(!std::fs::symlink_metadata(&tmp).unwrap().is_symlink());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
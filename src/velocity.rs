@@ -0,0 +1,93 @@
+use crate::mouse::Sample;
+use std::time::Duration;
+
+/// Report interval assumed before any has been measured (125Hz).
+const INITIAL_PERIOD: f32 = 0.008;
+
+/// How many expected intervals a velocity is held after the last report
+/// before it is considered stale and drops to zero.
+const HOLD_FACTOR: f32 = 1.5;
+
+/// Turns timestamped mouse reports into a velocity in counts per second.
+///
+/// Each report's counts are divided by the kernel-measured time since the
+/// previous report, so the result does not depend on when the main loop
+/// happened to wake up. The velocity is held until the next report is
+/// overdue, which also removes the sawtooth between sparse reports.
+pub struct ReportVelocity {
+    last_time: Option<Duration>,
+    /// Running estimate of the interval between consecutive reports, in seconds.
+    period: f32,
+    /// Interval of the most recent report, in seconds.
+    last_interval: f32,
+    vel: (f32, f32),
+    /// Gaps longer than this mean the mouse had stopped, not a slow report.
+    max_gap: f32,
+}
+
+impl ReportVelocity {
+    /// `max_gap` is the idle timeout in seconds: a longer gap between reports
+    /// starts a new motion instead of measuring a (very slow) one.
+    pub fn new(max_gap: f32) -> Self {
+        Self {
+            last_time: None,
+            period: INITIAL_PERIOD,
+            last_interval: INITIAL_PERIOD,
+            vel: (0.0, 0.0),
+            max_gap,
+        }
+    }
+
+    /// Estimated report interval in seconds.
+    pub fn period(&self) -> f32 {
+        self.period
+    }
+
+    pub fn set_max_gap(&mut self, max_gap: f32) {
+        self.max_gap = max_gap;
+    }
+
+    pub fn reset(&mut self) {
+        self.last_time = None;
+        self.vel = (0.0, 0.0);
+    }
+
+    /// Feed the reports received since the last call and return the current
+    /// velocity at time `now` (same clock as the samples).
+    pub fn update(&mut self, samples: &[Sample], now: Duration) -> (f32, f32) {
+        let mut counts = (0i64, 0i64);
+        let mut span = 0.0f32;
+        for s in samples {
+            let interval = match self.last_time {
+                Some(prev) if s.time > prev => {
+                    let gap = (s.time - prev).as_secs_f32();
+                    if gap <= self.max_gap {
+                        self.period += 0.1 * (gap - self.period);
+                        gap
+                    } else {
+                        self.period
+                    }
+                }
+                // Same timestamp as the previous report, or first report of
+                // a new motion: assume the usual spacing.
+                _ => self.period,
+            };
+            self.last_time = Some(s.time);
+            self.last_interval = interval;
+            counts.0 += s.dx as i64;
+            counts.1 += s.dy as i64;
+            span += interval;
+        }
+        if span > 0.0 {
+            self.vel = (counts.0 as f32 / span, counts.1 as f32 / span);
+        }
+
+        if let Some(last) = self.last_time {
+            let age = now.saturating_sub(last).as_secs_f32();
+            if age > self.last_interval.max(self.period) * HOLD_FACTOR {
+                self.vel = (0.0, 0.0);
+            }
+        }
+        self.vel
+    }
+}
@@ -1,73 +1,307 @@
 use evdev::uinput::VirtualDeviceBuilder;
-use evdev::{AbsInfo, AbsoluteAxisType, AttributeSet, BusType, InputId, Key, UinputAbsSetup};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, AttributeSetRef, EventType, FFEffectType,
+    InputEventKind, InputId, Key, UInputEventType, UinputAbsSetup,
+};
+use std::os::fd::AsRawFd;
 
-const STICK_MIN: i32 = -32767;
-const STICK_MAX: i32 = 32767;
+use crate::deadzone::Deadzone;
+use crate::ff::{FfCommand, FF_EFFECTS_MAX};
+use crate::gate::Gate;
+use crate::identity::{Identity, Preset, StickRange};
+use crate::profile::{PadButton, Stick};
+
+pub const STICK_MIN: i32 = -32767;
+pub const STICK_MAX: i32 = 32767;
+
+/// Analog trigger range (ABS_Z / ABS_RZ), as on common USB pads.
+pub const TRIGGER_MAX: i32 = 255;
+
+/// Buttons and analog triggers of the pad at one instant.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PadState {
+    /// One bit per `PadButton`.
+    pub buttons: u32,
+    /// L2 and R2 analog values (0-1) on top of the digital buttons, which
+    /// push their trigger fully.
+    pub triggers: [f32; 2],
+}
+
+impl PadState {
+    pub fn press(&mut self, button: PadButton) {
+        self.buttons |= 1 << button as u32;
+    }
+
+    pub fn pressed(&self, button: PadButton) -> bool {
+        self.buttons & (1 << button as u32) != 0
+    }
+
+    /// Analog trigger position, 0 to `TRIGGER_MAX`.
+    fn trigger(&self, button: PadButton, analog: f32) -> i32 {
+        if self.pressed(button) {
+            TRIGGER_MAX
+        } else {
+            (analog * TRIGGER_MAX as f32).round() as i32
+        }
+    }
+
+    /// For a pad without analog triggers: an analog value past half way
+    /// presses the button instead.
+    fn digital_triggers(mut self) -> Self {
+        for (i, button) in [PadButton::L2, PadButton::R2].into_iter().enumerate() {
+            if self.triggers[i] >= 0.5 {
+                self.press(button);
+            }
+        }
+        self.triggers = [0.0; 2];
+        self
+    }
+
+    fn hat(&self) -> (i32, i32) {
+        let [up, down, left, right] = [
+            PadButton::Up,
+            PadButton::Down,
+            PadButton::Left,
+            PadButton::Right,
+        ]
+        .map(|b| self.pressed(b) as i32);
+        (right - left, down - up)
+    }
+}
 
 pub struct VirtualPad {
     device: evdev::uinput::VirtualDevice,
-    axis_x: AbsoluteAxisType,
-    axis_y: AbsoluteAxisType,
+    /// The controller it passes for: its keys and axis ranges.
+    preset: Preset,
+    stick_range: StickRange,
+    deadzone: Deadzone,
+    gate: Gate,
+    /// Buttons and triggers as last emitted.
+    state: PadState,
+    /// Offers force feedback, so games' requests need answering.
+    ff: bool,
 }
 
 impl VirtualPad {
-    pub fn new(use_left_stick: bool) -> std::io::Result<Self> {
-        let abs = |axis: AbsoluteAxisType| -> UinputAbsSetup {
-            UinputAbsSetup::new(axis, AbsInfo::new(0, STICK_MIN, STICK_MAX, 0, 0, 1))
+    /// `ff`: force feedback effect types to offer, if any.
+    pub fn new(
+        identity: &Identity,
+        deadzone: Deadzone,
+        gate: Gate,
+        ff: Option<&AttributeSetRef<FFEffectType>>,
+    ) -> std::io::Result<Self> {
+        let preset = identity.preset;
+        let range = preset.stick_range();
+        let center = (range.min + range.max + 1) / 2;
+        let stick = |axis| {
+            let info = AbsInfo::new(center, range.min, range.max, range.fuzz, range.flat, 1);
+            UinputAbsSetup::new(axis, info)
+        };
+        let abs = |axis: AbsoluteAxisType, min: i32, max: i32| -> UinputAbsSetup {
+            UinputAbsSetup::new(axis, AbsInfo::new(0, min, max, 0, 0, 1))
         };
+        let trigger = |axis| abs(axis, 0, TRIGGER_MAX);
+        let hat = |axis| abs(axis, -1, 1);
 
         let mut keys = AttributeSet::<Key>::new();
-        keys.insert(Key::BTN_SOUTH);
-        keys.insert(Key::BTN_EAST);
-        keys.insert(Key::BTN_NORTH);
-        keys.insert(Key::BTN_WEST);
-        keys.insert(Key::BTN_TL2);
-        keys.insert(Key::BTN_TR2);
-
-        let device = VirtualDeviceBuilder::new()?
-            .name("m2joy Stick")
-            .input_id(InputId::new(BusType::BUS_VIRTUAL, 0x1234, 0x5678, 1))
+        for key in preset.keys() {
+            keys.insert(key);
+        }
+
+        let mut builder = VirtualDeviceBuilder::new()?;
+        if let Some(ff) = ff {
+            builder = builder.with_ff(ff)?.with_ff_effects_max(FF_EFFECTS_MAX);
+        }
+        builder = builder
+            .name(&identity.name)
+            .input_id(InputId::new(
+                identity.bus,
+                identity.vendor,
+                identity.product,
+                identity.version,
+            ))
             .with_keys(&keys)?
-            .with_absolute_axis(&abs(AbsoluteAxisType::ABS_X))?
-            .with_absolute_axis(&abs(AbsoluteAxisType::ABS_Y))?
-            .with_absolute_axis(&abs(AbsoluteAxisType::ABS_RX))?
-            .with_absolute_axis(&abs(AbsoluteAxisType::ABS_RY))?
-            .build()?;
-
-        let (axis_x, axis_y) = if use_left_stick {
-            (AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y)
-        } else {
-            (AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY)
-        };
+            .with_absolute_axis(&stick(AbsoluteAxisType::ABS_X))?
+            .with_absolute_axis(&stick(AbsoluteAxisType::ABS_Y))?
+            .with_absolute_axis(&stick(AbsoluteAxisType::ABS_RX))?
+            .with_absolute_axis(&stick(AbsoluteAxisType::ABS_RY))?
+            .with_absolute_axis(&hat(AbsoluteAxisType::ABS_HAT0X))?
+            .with_absolute_axis(&hat(AbsoluteAxisType::ABS_HAT0Y))?;
+        if preset.analog_triggers() {
+            builder = builder
+                .with_absolute_axis(&trigger(AbsoluteAxisType::ABS_Z))?
+                .with_absolute_axis(&trigger(AbsoluteAxisType::ABS_RZ))?;
+        }
+        let device = builder.build()?;
+        if ff.is_some() {
+            // Requests are picked up once per tick, never waited for
+            let fd = device.as_raw_fd();
+            unsafe {
+                libc::fcntl(
+                    fd,
+                    libc::F_SETFL,
+                    libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+                )
+            };
+        }
 
-        log::info!(
-            "Created virtual gamepad (output: {} stick)",
-            if use_left_stick { "left" } else { "right" }
-        );
+        log::info!("Created virtual gamepad '{}'", identity.name);
 
         Ok(Self {
             device,
-            axis_x,
-            axis_y,
+            preset,
+            stick_range: range,
+            deadzone,
+            gate,
+            state: PadState::default(),
+            ff: ff.is_some(),
         })
     }
 
-    pub fn emit_stick(&mut self, x: i32, y: i32) -> std::io::Result<()> {
-        let x = x.clamp(STICK_MIN, STICK_MAX);
-        let y = y.clamp(STICK_MIN, STICK_MAX);
-        self.device.emit(&[
-            evdev::InputEvent::new_now(evdev::EventType::ABSOLUTE, self.axis_x.0, x),
-            evdev::InputEvent::new_now(evdev::EventType::ABSOLUTE, self.axis_y.0, y),
-            evdev::InputEvent::new_now(evdev::EventType::SYNCHRONIZATION, 0, 0),
-        ])
+    /// Switch deadzone and gate, e.g. for another profile.
+    pub fn set_shaping(&mut self, deadzone: Deadzone, gate: Gate) {
+        self.deadzone = deadzone;
+        self.gate = gate;
+    }
+
+    /// Deadzone compensation and gate shaping of a stick position in
+    /// fractions of full deflection.
+    pub fn shape(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = self.deadzone.apply(x, y);
+        self.gate.apply(x, y)
     }
 
-    /// Emit L2/R2 trigger button state.
-    pub fn emit_triggers(&mut self, l2: bool, r2: bool) -> std::io::Result<()> {
+    /// Emit stick position after deadzone compensation and gate shaping.
+    pub fn emit_stick(&mut self, stick: Stick, x: i32, y: i32) -> std::io::Result<()> {
+        let full = STICK_MAX as f32;
+        let (nx, ny) = self.shape(x as f32 / full, y as f32 / full);
+        self.emit_stick_raw(
+            stick,
+            (nx * full).round() as i32,
+            (ny * full).round() as i32,
+        )
+    }
+
+    /// Emit stick position with only the range clamp (no deadzone or gate).
+    /// `x` and `y` go from `STICK_MIN` to `STICK_MAX` whatever the range the
+    /// pad reports.
+    pub fn emit_stick_raw(&mut self, stick: Stick, x: i32, y: i32) -> std::io::Result<()> {
+        let (x, y) = (self.stick_value(x), self.stick_value(y));
+        let (axis_x, axis_y) = match stick {
+            Stick::Left => (AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y),
+            Stick::Right => (AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY),
+        };
         self.device.emit(&[
-            evdev::InputEvent::new_now(evdev::EventType::KEY, Key::BTN_TL2.code(), l2 as i32),
-            evdev::InputEvent::new_now(evdev::EventType::KEY, Key::BTN_TR2.code(), r2 as i32),
+            evdev::InputEvent::new_now(evdev::EventType::ABSOLUTE, axis_x.0, x),
+            evdev::InputEvent::new_now(evdev::EventType::ABSOLUTE, axis_y.0, y),
             evdev::InputEvent::new_now(evdev::EventType::SYNCHRONIZATION, 0, 0),
         ])
     }
+
+    /// A stick value scaled from `STICK_MIN..=STICK_MAX` to the pad's range.
+    fn stick_value(&self, v: i32) -> i32 {
+        let StickRange { min, max, .. } = self.stick_range;
+        let v = (v.clamp(STICK_MIN, STICK_MAX) - STICK_MIN) as i64;
+        let span = (STICK_MAX - STICK_MIN) as i64;
+        min + ((v * (max - min) as i64 + span / 2) / span) as i32
+    }
+
+    /// Emit whatever changed since the last state, in a single report.
+    pub fn update(&mut self, state: &PadState) -> std::io::Result<()> {
+        let state = &if self.preset.analog_triggers() {
+            *state
+        } else {
+            state.digital_triggers()
+        };
+        if *state == self.state {
+            return Ok(());
+        }
+        let abs = |axis: AbsoluteAxisType, value: i32| {
+            evdev::InputEvent::new_now(evdev::EventType::ABSOLUTE, axis.0, value)
+        };
+        let old = self.state;
+        let mut events = Vec::new();
+        for button in PadButton::ALL {
+            let Some(key) = self.preset.key(button) else {
+                continue;
+            };
+            let pressed = state.pressed(button);
+            if pressed != old.pressed(button) {
+                events.push(evdev::InputEvent::new_now(
+                    evdev::EventType::KEY,
+                    key.code(),
+                    pressed as i32,
+                ));
+            }
+        }
+        let triggers = if self.preset.analog_triggers() {
+            &[
+                (PadButton::L2, AbsoluteAxisType::ABS_Z),
+                (PadButton::R2, AbsoluteAxisType::ABS_RZ),
+            ][..]
+        } else {
+            &[]
+        };
+        for (i, &(button, axis)) in triggers.iter().enumerate() {
+            let value = state.trigger(button, state.triggers[i]);
+            if value != old.trigger(button, old.triggers[i]) {
+                events.push(abs(axis, value));
+            }
+        }
+        let (hat_x, hat_y) = state.hat();
+        if (hat_x, hat_y) != old.hat() {
+            events.push(abs(AbsoluteAxisType::ABS_HAT0X, hat_x));
+            events.push(abs(AbsoluteAxisType::ABS_HAT0Y, hat_y));
+        }
+        events.push(evdev::InputEvent::new_now(
+            evdev::EventType::SYNCHRONIZATION,
+            0,
+            0,
+        ));
+        self.state = *state;
+        self.device.emit(&events)
+    }
+
+    /// Force feedback requests games made since the last call. Uploads and
+    /// erases are acknowledged right away; the effects play elsewhere.
+    pub fn ff_requests(&mut self, out: &mut Vec<FfCommand>) {
+        if !self.ff {
+            return;
+        }
+        let events: Vec<_> = match self.device.fetch_events() {
+            Ok(events) => events.collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                log::warn!("Failed to read force feedback requests: {}", e);
+                return;
+            }
+        };
+        for event in events {
+            match event.kind() {
+                InputEventKind::UInput(code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                    match self.device.process_ff_upload(event) {
+                        Ok(upload) => out.push(FfCommand::Upload(
+                            upload.effect_id() as u16,
+                            upload.effect(),
+                        )),
+                        Err(e) => log::warn!("Failed to take force feedback upload: {}", e),
+                    }
+                }
+                InputEventKind::UInput(code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                    match self.device.process_ff_erase(event) {
+                        Ok(erase) => out.push(FfCommand::Erase(erase.effect_id() as u16)),
+                        Err(e) => log::warn!("Failed to take force feedback erase: {}", e),
+                    }
+                }
+                _ if event.event_type() == EventType::FORCEFEEDBACK => {
+                    if event.code() == FFEffectType::FF_GAIN.0 {
+                        out.push(FfCommand::Gain(event.value() as u16));
+                    } else {
+                        out.push(FfCommand::Play(event.code(), event.value()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }